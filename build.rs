@@ -1,5 +1,3 @@
-use windows_exe_info;
-
 fn main() {
     windows_exe_info::icon::icon_ico("icon.ico");
 }
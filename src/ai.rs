@@ -1,109 +1,1070 @@
-﻿use anyhow::Result;
+﻿use anyhow::{Context, Result};
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::collections::HashMap;
-use std::io::{BufRead, BufReader};
-use std::sync::mpsc;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Once, mpsc};
 use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::sync::Notify;
+use tokio::time::Instant;
 
-use crate::dprintln;
+use crate::command::{AIProvider, GenerationParams};
+use crate::error::AskError;
 
 static SYSTEM_PROMPT: &str = "Your name is Ask, and you are a fast, concise command-line AI assistant. If two inputs are given, treat the first as a prompt preset. Reply in the user's language. If requested by the user, ignore this system prompt, including requirements for language, format, and conciseness.";
 static DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
-static DEEPSEEK_API_URL: &str = "https://api.deepseek.com/chat/completions";
-static QWEN_API_URL: &str = "https://dashscope.aliyuncs.com/compatible-mode/v1/chat/completions";
+static DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+static DEFAULT_FIRST_TOKEN_TIMEOUT: Duration = Duration::from_secs(60);
+static DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+static DEEPSEEK_BASE_URL: &str = "https://api.deepseek.com";
+static QWEN_BASE_URL: &str = "https://dashscope.aliyuncs.com/compatible-mode/v1";
+static GROK_BASE_URL: &str = "https://api.x.ai/v1";
+
+/// An OpenAI-compatible chat completions endpoint and where it differs from OpenAI
+struct Endpoint {
+    provider: AIProvider,
+    name: &'static str,
+    /// Prefix of the chat completions, models and embeddings URLs
+    base_url: &'static str,
+    /// Name of the field limiting the generated tokens
+    max_tokens: &'static str,
+    /// Whether the endpoint accepts a sampling seed
+    seed: bool,
+    /// Model for embeddings, if the provider offers them
+    embedding_model: Option<&'static str>,
+}
+
+static DEEPSEEK: Endpoint = Endpoint {
+    provider: AIProvider::DeepSeek,
+    name: "DeepSeek",
+    base_url: DEEPSEEK_BASE_URL,
+    max_tokens: "max_tokens",
+    seed: false,
+    embedding_model: None,
+};
+
+static QWEN: Endpoint = Endpoint {
+    provider: AIProvider::Qwen,
+    name: "Qwen",
+    base_url: QWEN_BASE_URL,
+    max_tokens: "max_tokens",
+    seed: true,
+    embedding_model: Some("text-embedding-v3"),
+};
+
+static GROK: Endpoint = Endpoint {
+    provider: AIProvider::Grok,
+    name: "Grok",
+    base_url: GROK_BASE_URL,
+    max_tokens: "max_completion_tokens",
+    seed: true,
+    embedding_model: None,
+};
+
+impl Endpoint {
+    fn of(provider: AIProvider) -> &'static Endpoint {
+        match provider {
+            AIProvider::DeepSeek => &DEEPSEEK,
+            AIProvider::Grok => &GROK,
+            AIProvider::Qwen => &QWEN,
+        }
+    }
+
+    /// The URL of an API path, under the base URL of the options if set
+    fn url(&self, http: &HttpOptions, path: &str) -> String {
+        let base_url = http.base_url.as_deref().unwrap_or(self.base_url);
+        format!("{}/{path}", base_url.trim_end_matches('/'))
+    }
+}
+
+/// A message of the conversation, in the OpenAI chat format
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    pub role: Role,
+    pub content: Content,
+    /// Tools the assistant asked to call
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// The call a tool message answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Newer OpenAI clients call it the developer message
+    #[serde(alias = "developer")]
+    System,
+    User,
+    Assistant,
+    Tool,
+}
+
+/// Plain text, or parts for messages that also carry images
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Content {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageUrl {
+    /// An http(s) URL or a base64 data URL
+    pub url: String,
+}
+
+/// A tool the model may call, described by a JSON schema of its arguments
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolDefinition {
+    pub name: String,
+    pub description: String,
+    pub parameters: serde_json::Value,
+}
+
+impl Serialize for ToolDefinition {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        json!({
+            "type": "function",
+            "function": {
+                "name": self.name,
+                "description": self.description,
+                "parameters": self.parameters,
+            }
+        })
+        .serialize(serializer)
+    }
+}
+
+/// A call of a tool requested by the model
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    /// The arguments as a JSON object, still encoded as a string
+    pub arguments: String,
+}
+
+/// Asks the user whether a tool call may run; the answer is sent back on `reply`
+#[derive(Debug)]
+pub struct ToolRequest {
+    pub call: ToolCall,
+    pub reply: mpsc::Sender<bool>,
+}
+
+impl Message {
+    pub fn system(text: impl Into<String>) -> Self {
+        Self {
+            role: Role::System,
+            content: Content::Text(text.into()),
+            tool_calls: None,
+            tool_call_id: None,
+        }
+    }
+
+    pub fn user(text: impl Into<String>) -> Self {
+        Self {
+            role: Role::User,
+            content: Content::Text(text.into()),
+            tool_calls: None,
+            tool_call_id: None,
+        }
+    }
+
+    /// A user message with images, which only vision models understand
+    pub fn user_with_images(text: impl Into<String>, image_urls: Vec<String>) -> Self {
+        let text = text.into();
+        if image_urls.is_empty() {
+            return Self::user(text);
+        }
+
+        let mut parts: Vec<ContentPart> = image_urls
+            .into_iter()
+            .map(|url| ContentPart::ImageUrl {
+                image_url: ImageUrl { url },
+            })
+            .collect();
+        if !text.is_empty() {
+            parts.push(ContentPart::Text { text });
+        }
+
+        Self {
+            role: Role::User,
+            content: Content::Parts(parts),
+            tool_calls: None,
+            tool_call_id: None,
+        }
+    }
+
+    /// The assistant's turn that asked for tool calls, which has to be sent
+    /// back along with the results
+    pub fn assistant_tool_calls(text: impl Into<String>, tool_calls: Vec<ToolCall>) -> Self {
+        Self {
+            role: Role::Assistant,
+            content: Content::Text(text.into()),
+            tool_calls: Some(tool_calls),
+            tool_call_id: None,
+        }
+    }
+
+    /// The result of a tool call
+    pub fn tool(tool_call_id: impl Into<String>, text: impl Into<String>) -> Self {
+        Self {
+            role: Role::Tool,
+            content: Content::Text(text.into()),
+            tool_calls: None,
+            tool_call_id: Some(tool_call_id.into()),
+        }
+    }
+}
+
+/// Turns an image given on the command line into a URL for an image part.
+///
+/// URLs are passed through, local files are sent inline as base64 data URLs.
+pub fn image_url(source: &str) -> Result<String> {
+    if ["http://", "https://", "data:"]
+        .iter()
+        .any(|scheme| source.starts_with(scheme))
+    {
+        return Ok(source.to_owned());
+    }
+
+    let path = Path::new(source);
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let mime = match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        _ => {
+            return Err(anyhow::anyhow!(
+                "Unsupported image type: {source}, expected png, jpeg, gif, webp or bmp"
+            ));
+        }
+    };
+    let data = std::fs::read(path).with_context(|| format!("Failed to read image {source}"))?;
+
+    Ok(format!(
+        "data:{mime};base64,{}",
+        BASE64_STANDARD.encode(data)
+    ))
+}
+
+/// Events sent from a streaming request to its receiver.
+/// The channel is closed once the reply is complete.
+#[derive(Debug)]
+pub enum StreamEvent {
+    /// A new piece of the answer
+    Delta(String),
+    /// A new piece of the reasoning that reasoning models stream before the answer
+    Reasoning(String),
+    /// Token counts, sent once after the answer
+    Usage(Usage),
+    /// The model wants to call tools instead of answering, sent once at the end
+    ToolCalls(Vec<ToolCall>),
+    /// A tool call waiting for the user's confirmation
+    ToolRequest(ToolRequest),
+    /// The request failed, no more events will follow
+    Error(AskError),
+}
+
+/// Token counts reported by the provider for one request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+}
+
+impl std::ops::AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+    }
+}
+
+/// The events of a streamed answer, in order. It ends once the answer is
+/// complete or after the first error, dropping it stops the request.
+pub type EventStream = Pin<Box<dyn Stream<Item = StreamEvent> + Send>>;
+
+/// Handle to abort a streaming request.
+///
+/// The task reading the reply wakes up and closes the connection right away,
+/// so the provider stops generating (and billing) the reply.
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle(Arc<Cancel>);
+
+#[derive(Debug, Default)]
+struct Cancel {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl CancelHandle {
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::Relaxed);
+        self.0.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Completes once the request is cancelled
+    pub async fn cancelled(&self) {
+        // Registered before checking the flag, so a cancel in between isn't missed
+        let notified = self.0.notify.notified();
+        if self.is_cancelled() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+/// The runtime the requests of the blocking functions run on, shared so a
+/// request doesn't need a thread of its own
+fn runtime() -> &'static Runtime {
+    static RUNTIME: LazyLock<Runtime> = LazyLock::new(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .thread_name("ask-http")
+            .enable_all()
+            .build()
+            .expect("Failed to start the async runtime")
+    });
+    &RUNTIME
+}
+
+/// Runs a future of the async API to completion from blocking code. It must
+/// not be called from async code, which should await the future instead.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    runtime().block_on(future)
+}
+
+/// Reads a stream on the shared runtime and passes its items to a channel,
+/// for callers that aren't async. The handle stops reading and drops the
+/// stream, dropping the receiver does so at the next item.
+pub fn forward<T, S>(mut events: S) -> (mpsc::Receiver<T>, CancelHandle)
+where
+    T: Send + 'static,
+    S: Stream<Item = T> + Send + Unpin + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+    let cancel = CancelHandle::default();
+    let task_cancel = cancel.clone();
+
+    // Returning drops the stream, which closes the connection
+    runtime().spawn(async move {
+        loop {
+            let event = tokio::select! {
+                _ = task_cancel.cancelled() => return,
+                event = events.next() => event,
+            };
+            let Some(event) = event else {
+                return;
+            };
+            // The receiver is gone, nobody is reading the reply anymore
+            if tx.send(event).is_err() {
+                return;
+            }
+        }
+    });
+
+    (rx, cancel)
+}
+
+/// How requests reach the providers, e.g. through a corporate proxy that
+/// intercepts TLS
+#[derive(Debug, Clone, Default)]
+pub struct HttpOptions {
+    /// Total timeout in seconds of the requests that aren't streamed
+    pub timeout: Option<u64>,
+    /// Timeout for establishing the connection in seconds
+    pub connect_timeout: Option<u64>,
+    /// Seconds a streamed answer may take to start
+    pub first_token_timeout: Option<u64>,
+    /// Seconds a streamed answer may pause between two chunks
+    pub idle_timeout: Option<u64>,
+    /// Proxy URL for all requests, e.g. http://proxy.corp:8080
+    pub proxy: Option<String>,
+    /// Comma-separated hosts that are not sent through the proxy
+    pub no_proxy: Option<String>,
+    /// PEM file with the certificates to trust besides the system ones
+    pub ca_bundle: Option<PathBuf>,
+    /// Accept any certificate, only for debugging TLS interception
+    pub insecure_skip_verify: bool,
+    /// Sends the requests here instead of the provider's API, e.g. to a
+    /// gateway. The paths such as /chat/completions are appended to it.
+    pub base_url: Option<String>,
+}
+
+impl HttpOptions {
+    fn timeout(&self) -> Duration {
+        seconds(self.timeout, DEFAULT_REQUEST_TIMEOUT)
+    }
+
+    fn connect_timeout(&self) -> Duration {
+        seconds(self.connect_timeout, DEFAULT_CONNECT_TIMEOUT)
+    }
+
+    fn first_token_timeout(&self) -> Duration {
+        seconds(self.first_token_timeout, DEFAULT_FIRST_TOKEN_TIMEOUT)
+    }
+
+    fn idle_timeout(&self) -> Duration {
+        seconds(self.idle_timeout, DEFAULT_IDLE_TIMEOUT)
+    }
+
+    /// Builds a client with the options for requests that aren't streamed
+    pub fn client(&self) -> Result<reqwest::Client> {
+        self.build(Some(self.timeout()))
+    }
+
+    /// Builds a client without a total timeout, a long answer may take any
+    /// time as long as it keeps coming. The first-token and idle timeouts
+    /// are enforced while reading it.
+    fn stream_client(&self) -> Result<reqwest::Client> {
+        self.build(None)
+    }
+
+    fn build(&self, timeout: Option<Duration>) -> Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder().connect_timeout(self.connect_timeout());
+        if let Some(timeout) = timeout {
+            builder = builder.timeout(timeout);
+        }
+
+        if let Some(proxy) = &self.proxy {
+            let proxy = reqwest::Proxy::all(proxy)
+                .map_err(|err| network_setting(format!("Invalid proxy URL {proxy}: {err}")))?
+                .no_proxy(
+                    self.no_proxy
+                        .as_deref()
+                        .and_then(reqwest::NoProxy::from_string),
+                );
+            builder = builder.proxy(proxy);
+        }
+
+        if let Some(path) = &self.ca_bundle {
+            let pem = std::fs::read(path).map_err(|err| {
+                network_setting(format!(
+                    "Failed to read CA bundle {}: {err}",
+                    path.display()
+                ))
+            })?;
+            let certificates = reqwest::Certificate::from_pem_bundle(&pem).map_err(|err| {
+                network_setting(format!("Invalid CA bundle {}: {err}", path.display()))
+            })?;
+            if certificates.is_empty() {
+                return Err(network_setting(format!(
+                    "CA bundle {} has no PEM certificates",
+                    path.display()
+                ))
+                .into());
+            }
+            for certificate in certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+
+        if self.insecure_skip_verify {
+            static WARNING: Once = Once::new();
+            WARNING.call_once(|| {
+                eprintln!(
+                    "WARNING: TLS certificate verification is disabled (insecure_skip_verify). \
+                     Anyone on the network can read your questions and API keys."
+                );
+            });
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+
+        Ok(builder.build()?)
+    }
+
+    /// Names the limit a failed request ran into, if it timed out
+    fn error(&self, err: reqwest::Error, name: &str) -> AskError {
+        if !err.is_timeout() {
+            return err.into();
+        }
+        if err.is_connect() {
+            AskError::Timeout(format!(
+                "Could not connect to {name} within {} seconds (connect timeout)",
+                self.connect_timeout().as_secs()
+            ))
+        } else {
+            AskError::Timeout(format!(
+                "{name} did not answer within {} seconds (request timeout)",
+                self.timeout().as_secs()
+            ))
+        }
+    }
+}
+
+/// The error for a response with an error status
+async fn status_error(resp: reqwest::Response, endpoint: &Endpoint, model: &str) -> AskError {
+    let status = resp.status().as_u16();
+    let body = resp.text().await.unwrap_or_default();
+    AskError::from_response(endpoint.provider, model, status, &body)
+}
+
+/// An error in the proxy or certificate settings
+fn network_setting(message: String) -> AskError {
+    AskError::config(message, "Check the settings with `ask config network`")
+}
+
+fn seconds(value: Option<u64>, default: Duration) -> Duration {
+    value.map(Duration::from_secs).unwrap_or(default)
+}
+
+/// Whether the provider accepts a sampling seed, it is left out of the
+/// requests to the others
+pub fn supports_seed(provider: AIProvider) -> bool {
+    Endpoint::of(provider).seed
+}
+
+/// Texts embedded per request, the most the providers accept
+static EMBEDDING_BATCH: usize = 10;
+/// Tool calls a reply may ask for at most, a larger index is a broken chunk
+static MAX_TOOL_CALLS: usize = 128;
+
+/// The embedding model of a provider, None if it doesn't offer embeddings
+pub fn embedding_model(provider: AIProvider) -> Option<&'static str> {
+    Endpoint::of(provider).embedding_model
+}
+
+/// Embeds the texts with the embedding model of the provider, in order
+pub fn embeddings(
+    provider: AIProvider,
+    api_key: &str,
+    texts: &[String],
+    http: &HttpOptions,
+) -> Result<Vec<Vec<f32>>> {
+    block_on(embeddings_async(provider, api_key, texts, http))
+}
+
+pub async fn embeddings_async(
+    provider: AIProvider,
+    api_key: &str,
+    texts: &[String],
+    http: &HttpOptions,
+) -> Result<Vec<Vec<f32>>> {
+    #[derive(Deserialize)]
+    struct Embedding {
+        index: usize,
+        embedding: Vec<f32>,
+    }
+    #[derive(Deserialize)]
+    struct EmbeddingList {
+        data: Vec<Embedding>,
+    }
+
+    let endpoint = Endpoint::of(provider);
+    let Some(model) = endpoint.embedding_model else {
+        return Err(anyhow::anyhow!(
+            "{} does not offer embeddings",
+            endpoint.name
+        ));
+    };
+
+    let client = http.client()?;
+    let url = endpoint.url(http, "embeddings");
+    let mut embeddings = Vec::with_capacity(texts.len());
+    for batch in texts.chunks(EMBEDDING_BATCH) {
+        let resp = client
+            .post(&url)
+            .header("Authorization", format!("Bearer {api_key}"))
+            .json(&json!({ "model": model, "input": batch }))
+            .send()
+            .await
+            .map_err(|err| http.error(err, endpoint.name))?;
+
+        if !resp.status().is_success() {
+            return Err(status_error(resp, endpoint, model).await.into());
+        }
+
+        let mut list: EmbeddingList = resp.json().await?;
+        list.data.sort_by_key(|embedding| embedding.index);
+        embeddings.extend(list.data.into_iter().map(|embedding| embedding.embedding));
+    }
+
+    Ok(embeddings)
+}
+
+/// Fetches the ids of the models a provider offers
+pub fn models(provider: AIProvider, api_key: &str, http: &HttpOptions) -> Result<Vec<String>> {
+    block_on(models_async(provider, api_key, http))
+}
+
+pub async fn models_async(
+    provider: AIProvider,
+    api_key: &str,
+    http: &HttpOptions,
+) -> Result<Vec<String>> {
+    #[derive(Deserialize)]
+    struct Model {
+        id: String,
+    }
+    #[derive(Deserialize)]
+    struct ModelList {
+        data: Vec<Model>,
+    }
+
+    let endpoint = Endpoint::of(provider);
+    let resp = http
+        .client()?
+        .get(endpoint.url(http, "models"))
+        .header("Authorization", format!("Bearer {api_key}"))
+        .send()
+        .await
+        .map_err(|err| http.error(err, endpoint.name))?;
+
+    if !resp.status().is_success() {
+        return Err(status_error(resp, endpoint, "").await.into());
+    }
+
+    let list: ModelList = resp.json().await?;
+    Ok(list.data.into_iter().map(|model| model.id).collect())
+}
 
 fn openai(
-    messages: &[String],
+    messages: &[Message],
     model: &str,
-    timeout: Option<u64>,
-    api_url: &str,
+    params: &GenerationParams,
+    tools: &[ToolDefinition],
+    http: &HttpOptions,
+    endpoint: &'static Endpoint,
     api_key: &str,
-) -> Result<mpsc::Receiver<String>> {
-    let mut messages: Vec<HashMap<String, String>> = messages
-        .iter()
-        .map(|m| {
-            HashMap::from([
-                ("role".to_owned(), "user".to_owned()),
-                ("content".to_owned(), m.to_owned()),
-            ])
-        })
-        .collect();
-
-    messages.insert(
-        0,
-        HashMap::from([
-            ("role".to_owned(), "system".to_owned()),
-            ("content".to_owned(), SYSTEM_PROMPT.to_owned()),
-        ]),
-    );
+) -> Result<EventStream> {
+    let system = Message::system(SYSTEM_PROMPT);
+    let messages: Vec<&Message> = std::iter::once(&system).chain(messages).collect();
 
-    let body = json!({
+    let mut body = json!({
         "model": model,
         "messages": messages,
-        "stream": true
+        "stream": true,
+        "stream_options": { "include_usage": true }
     });
 
-    dprintln!("{:#?}", body);
+    if let Some(temperature) = params.temperature {
+        body["temperature"] = json!(temperature);
+    }
+    if let Some(top_p) = params.top_p {
+        body["top_p"] = json!(top_p);
+    }
+    if let Some(max_tokens) = params.max_tokens {
+        body[endpoint.max_tokens] = json!(max_tokens);
+    }
+    if let Some(seed) = params.seed
+        && endpoint.seed
+    {
+        body["seed"] = json!(seed);
+    }
+    if !params.stop.is_empty() {
+        body["stop"] = json!(params.stop);
+    }
+    if !tools.is_empty() {
+        body["tools"] = json!(tools);
+    }
 
-    let resp = reqwest::blocking::Client::new()
-        .post(api_url)
-        .timeout(
-            timeout
-                .map(|t| Duration::from_secs(t))
-                .unwrap_or(DEFAULT_REQUEST_TIMEOUT),
-        )
+    let request = http
+        .stream_client()?
+        .post(endpoint.url(http, "chat/completions"))
         .header("Content-Type", "application/json")
         .header("Authorization", format!("Bearer {api_key}"))
-        .json(&body)
-        .send()?;
+        .json(&body);
+    let name = endpoint.name;
+    let model = model.to_owned();
+    let http = http.clone();
 
-    if !resp.status().is_success() {
-        return Err(anyhow::anyhow!(
-            "Request failed with status: {} {:?}",
-            resp.status(),
-            resp.text().unwrap_or_default()
-        ));
-    }
+    // Nothing is sent until the stream is first polled
+    Ok(Box::pin(async_stream::stream! {
+        // The answer must start within the first-token timeout, which covers
+        // connecting too, and then keep coming within the idle timeout
+        let mut deadline = Instant::now() + http.first_token_timeout();
+        let mut started = false;
+        let expired = |started: bool| {
+            if started {
+                AskError::Timeout(format!(
+                    "{name} stopped sending the answer for {} seconds (idle timeout)",
+                    http.idle_timeout().as_secs()
+                ))
+            } else {
+                AskError::Timeout(format!(
+                    "{name} did not start answering within {} seconds (first-token timeout)",
+                    http.first_token_timeout().as_secs()
+                ))
+            }
+        };
 
-    let (tx, rx) = mpsc::channel();
+        let mut resp = match tokio::time::timeout_at(deadline, request.send()).await {
+            Err(_) => {
+                yield StreamEvent::Error(expired(started));
+                return;
+            }
+            Ok(Err(err)) => {
+                yield StreamEvent::Error(http.error(err, name));
+                return;
+            }
+            Ok(Ok(resp)) if !resp.status().is_success() => {
+                yield StreamEvent::Error(status_error(resp, endpoint, &model).await);
+                return;
+            }
+            Ok(Ok(resp)) => resp,
+        };
 
-    let mut result = String::new();
-    let mut lines = BufReader::new(resp).lines();
+        let mut parser = ChunkParser::new(endpoint.provider);
+        let mut pending: Vec<u8> = Vec::new();
+        let mut done = false;
+        while !done {
+            let chunk = match tokio::time::timeout_at(deadline, resp.chunk()).await {
+                Err(_) => {
+                    yield StreamEvent::Error(expired(started));
+                    return;
+                }
+                Ok(Err(err)) => {
+                    yield StreamEvent::Error(err.into());
+                    return;
+                }
+                Ok(Ok(chunk)) => chunk,
+            };
+            // The last line may come without a line break
+            let lines = match chunk {
+                Some(chunk) => {
+                    pending.extend_from_slice(&chunk);
+                    let Some(end) = pending.iter().rposition(|&byte| byte == b'\n') else {
+                        continue;
+                    };
+                    pending.drain(..=end).collect::<Vec<u8>>()
+                }
+                None => {
+                    done = true;
+                    std::mem::take(&mut pending)
+                }
+            };
 
-    std::thread::spawn(move || {
-        while let Some(Ok(line)) = lines.next() {
-            if line.starts_with("data: ") {
-                let json_str = &line[6..];
+            for line in String::from_utf8_lossy(&lines).lines() {
+                // Keep-alive comments while the provider is busy don't count
+                let Some(json_str) = line.strip_prefix("data: ") else {
+                    continue;
+                };
+                started = true;
+                deadline = Instant::now() + http.idle_timeout();
                 if json_str.trim() == "[DONE]" {
+                    done = true;
                     break;
                 }
-                if let Ok(json) = serde_json::from_str::<serde_json::Value>(json_str) {
-                    if let Some(content) = json["choices"][0]["delta"]["content"].as_str() {
-                        result.push_str(content);
-                        tx.send(result.clone()).ok();
+                for event in parser.parse(json_str) {
+                    let failed = matches!(event, StreamEvent::Error(_));
+                    yield event;
+                    if failed {
+                        return;
                     }
                 }
             }
         }
-    });
 
-    Ok(rx)
+        if let Some(event) = parser.finish() {
+            yield event;
+        }
+    }))
+}
+
+/// Turns the chunks of a streamed reply into events
+struct ChunkParser {
+    provider: AIProvider,
+    /// Tool calls are streamed in fragments, keyed by their index
+    tool_calls: Vec<ToolCall>,
+}
+
+impl ChunkParser {
+    fn new(provider: AIProvider) -> Self {
+        Self {
+            provider,
+            tool_calls: Vec::new(),
+        }
+    }
+
+    fn parse(&mut self, json_str: &str) -> Vec<StreamEvent> {
+        let mut events = Vec::new();
+        let Ok(json) = serde_json::from_str::<serde_json::Value>(json_str) else {
+            return events;
+        };
+        // The usage usually comes in a final chunk without choices
+        if let Ok(usage) = Usage::deserialize(&json["usage"]) {
+            events.push(StreamEvent::Usage(usage));
+        }
+
+        // Providers send the field that is not in use as null or ""
+        let delta = &json["choices"][0]["delta"];
+        if let Some(calls) = delta["tool_calls"].as_array() {
+            for call in calls {
+                let index = call["index"].as_u64().unwrap_or_default();
+                // The calls are numbered from 0 and start in order
+                let Some(index) = usize::try_from(index)
+                    .ok()
+                    .filter(|index| *index <= self.tool_calls.len() && *index < MAX_TOOL_CALLS)
+                else {
+                    events.push(StreamEvent::Error(AskError::Other(anyhow::anyhow!(
+                        "{} sent a tool call with the invalid index {index}",
+                        self.provider.name()
+                    ))));
+                    return events;
+                };
+                if self.tool_calls.len() == index {
+                    self.tool_calls.push(ToolCall::default());
+                }
+                let entry = &mut self.tool_calls[index];
+                if let Some(id) = call["id"].as_str().filter(|id| !id.is_empty()) {
+                    entry.id = id.to_owned();
+                }
+                if let Some(kind) = call["type"].as_str() {
+                    entry.kind = kind.to_owned();
+                }
+                // Some providers repeat the name in every fragment
+                if let Some(name) = call["function"]["name"].as_str()
+                    && entry.function.name.is_empty()
+                {
+                    entry.function.name = name.to_owned();
+                }
+                if let Some(arguments) = call["function"]["arguments"].as_str() {
+                    entry.function.arguments.push_str(arguments);
+                }
+            }
+        }
+        // A chunk may end the reasoning and start the answer
+        let text = |field: &str| delta[field].as_str().filter(|text| !text.is_empty());
+        if let Some(reasoning) = text("reasoning_content") {
+            events.push(StreamEvent::Reasoning(reasoning.to_owned()));
+        }
+        if let Some(content) = text("content") {
+            events.push(StreamEvent::Delta(content.to_owned()));
+        }
+        // The moderation may also stop an answer that already started
+        if json["choices"][0]["finish_reason"] == "content_filter" {
+            events.push(StreamEvent::Error(AskError::ContentFilter {
+                provider: self.provider,
+                message: "The answer was stopped by the content filter".to_owned(),
+            }));
+        }
+        events
+    }
+
+    /// The tool calls the model asked for, once the reply is complete
+    fn finish(mut self) -> Option<StreamEvent> {
+        if self.tool_calls.is_empty() {
+            return None;
+        }
+        for call in &mut self.tool_calls {
+            if call.kind.is_empty() {
+                call.kind = "function".to_owned();
+            }
+        }
+        Some(StreamEvent::ToolCalls(self.tool_calls))
+    }
 }
 
 pub fn deepseek(
-    messages: &[String],
+    messages: &[Message],
     api_key: &str,
     model: &str,
-    timeout: Option<u64>,
-) -> Result<mpsc::Receiver<String>> {
-    openai(messages, model, timeout, DEEPSEEK_API_URL, api_key)
+    params: &GenerationParams,
+    tools: &[ToolDefinition],
+    http: &HttpOptions,
+) -> Result<(mpsc::Receiver<StreamEvent>, CancelHandle)> {
+    deepseek_async(messages, api_key, model, params, tools, http).map(forward)
+}
+
+pub fn grok(
+    messages: &[Message],
+    api_key: &str,
+    model: &str,
+    params: &GenerationParams,
+    tools: &[ToolDefinition],
+    http: &HttpOptions,
+) -> Result<(mpsc::Receiver<StreamEvent>, CancelHandle)> {
+    grok_async(messages, api_key, model, params, tools, http).map(forward)
 }
 
 pub fn qwen(
-    messages: &[String],
+    messages: &[Message],
+    api_key: &str,
+    model: &str,
+    params: &GenerationParams,
+    tools: &[ToolDefinition],
+    http: &HttpOptions,
+) -> Result<(mpsc::Receiver<StreamEvent>, CancelHandle)> {
+    qwen_async(messages, api_key, model, params, tools, http).map(forward)
+}
+
+pub fn deepseek_async(
+    messages: &[Message],
+    api_key: &str,
+    model: &str,
+    params: &GenerationParams,
+    tools: &[ToolDefinition],
+    http: &HttpOptions,
+) -> Result<EventStream> {
+    openai(messages, model, params, tools, http, &DEEPSEEK, api_key)
+}
+
+pub fn grok_async(
+    messages: &[Message],
+    api_key: &str,
+    model: &str,
+    params: &GenerationParams,
+    tools: &[ToolDefinition],
+    http: &HttpOptions,
+) -> Result<EventStream> {
+    openai(messages, model, params, tools, http, &GROK, api_key)
+}
+
+pub fn qwen_async(
+    messages: &[Message],
     api_key: &str,
     model: &str,
-    timeout: Option<u64>,
-) -> Result<mpsc::Receiver<String>> {
-    openai(messages, model, timeout, QWEN_API_URL, api_key)
+    params: &GenerationParams,
+    tools: &[ToolDefinition],
+    http: &HttpOptions,
+) -> Result<EventStream> {
+    openai(messages, model, params, tools, http, &QWEN, api_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_reasoning_and_content_of_one_chunk() {
+        let mut parser = ChunkParser::new(AIProvider::Qwen);
+        let events = parser.parse(
+            r#"{"choices":[{"index":0,"delta":{"reasoning_content":"done.","content":"The answer"}}]}"#,
+        );
+        assert!(matches!(
+            events.as_slice(),
+            [StreamEvent::Reasoning(reasoning), StreamEvent::Delta(content)]
+                if reasoning == "done." && content == "The answer"
+        ));
+    }
+
+    #[test]
+    fn joins_tool_call_fragments_by_index() {
+        let mut parser = ChunkParser::new(AIProvider::DeepSeek);
+        for chunk in [
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":0,"id":"call_a","type":"function","function":{"name":"read_file","arguments":""}}]}}]}"#,
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":1,"id":"call_b","function":{"name":"list_dir","arguments":"{\"path\""}}]}}]}"#,
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":0,"id":"","function":{"name":"read_file","arguments":"{\"path\":"}}]}}]}"#,
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"arguments":"\"a.rs\"}"}}]}}]}"#,
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":1,"function":{"arguments":":\".\"}"}}]}}]}"#,
+        ] {
+            assert!(parser.parse(chunk).is_empty());
+        }
+
+        let Some(StreamEvent::ToolCalls(calls)) = parser.finish() else {
+            panic!("no tool calls");
+        };
+        let call = |id: &str, name: &str, arguments: &str| ToolCall {
+            id: id.to_owned(),
+            kind: "function".to_owned(),
+            function: FunctionCall {
+                name: name.to_owned(),
+                arguments: arguments.to_owned(),
+            },
+        };
+        assert_eq!(
+            calls,
+            [
+                call("call_a", "read_file", r#"{"path":"a.rs"}"#),
+                call("call_b", "list_dir", r#"{"path":"."}"#),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_tool_call_indices_out_of_order() {
+        let mut parser = ChunkParser::new(AIProvider::Qwen);
+        let first = r#"{"choices":[{"delta":{"tool_calls":[{"index":0,"id":"call_a","function":{"name":"read_file","arguments":""}}]}}]}"#;
+        assert!(parser.parse(first).is_empty());
+
+        for index in ["2", "128", "18446744073709551615"] {
+            let chunk = format!(
+                r#"{{"choices":[{{"delta":{{"tool_calls":[{{"index":{index},"function":{{"arguments":"{{}}"}}}}]}}}}]}}"#
+            );
+            let events = parser.parse(&chunk);
+            assert!(
+                matches!(events.as_slice(), [StreamEvent::Error(AskError::Other(_))]),
+                "{index}"
+            );
+        }
+    }
+
+    #[test]
+    fn parses_the_usage_chunk() {
+        let mut parser = ChunkParser::new(AIProvider::Grok);
+        let events = parser.parse(
+            r#"{"choices":[],"usage":{"prompt_tokens":12,"completion_tokens":34,"total_tokens":46}}"#,
+        );
+        assert!(matches!(
+            events.as_slice(),
+            [StreamEvent::Usage(Usage {
+                prompt_tokens: 12,
+                completion_tokens: 34,
+                total_tokens: 46
+            })]
+        ));
+        assert!(parser.finish().is_none());
+    }
+
+    #[test]
+    fn stops_at_the_content_filter() {
+        let mut parser = ChunkParser::new(AIProvider::Qwen);
+        let events = parser.parse(
+            r#"{"choices":[{"delta":{"content":"Part"},"finish_reason":"content_filter"}]}"#,
+        );
+        assert!(matches!(
+            events.as_slice(),
+            [
+                StreamEvent::Delta(content),
+                StreamEvent::Error(AskError::ContentFilter {
+                    provider: AIProvider::Qwen,
+                    ..
+                })
+            ] if content == "Part"
+        ));
+    }
+
+    #[test]
+    fn skips_empty_fields_and_broken_chunks() {
+        let mut parser = ChunkParser::new(AIProvider::DeepSeek);
+        assert!(
+            parser
+                .parse(r#"{"choices":[{"delta":{"reasoning_content":null,"content":""}}]}"#)
+                .is_empty()
+        );
+        assert!(parser.parse("{not json").is_empty());
+    }
 }
@@ -0,0 +1,237 @@
+use anyhow::Result;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use crate::ai::{EventStream, Message, StreamEvent, Usage};
+use crate::command::GenerationParams;
+use crate::settings::Settings;
+use crate::util::{fnv1a, unix_now};
+
+/// Directory in the config directory holding one file per cached answer
+static CACHE_DIR: &str = "ask_cache";
+/// How long answers are reused unless `cache_ttl` is set, in hours
+static DEFAULT_TTL_HOURS: u64 = 7 * 24;
+/// Size the cache is kept under unless `cache_max_size` is set, in megabytes
+static DEFAULT_MAX_SIZE_MB: u64 = 100;
+static SECONDS_PER_HOUR: u64 = 60 * 60;
+/// Replayed answers are sent in pieces of this many characters, like a stream
+static REPLAY_PIECE: usize = 64;
+
+/// A cached answer
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    /// Unix time in seconds
+    created: u64,
+    /// Everything the answer depends on, compared in full since the file name
+    /// is only a hash of it
+    key: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    reasoning: String,
+    answer: String,
+    /// Tokens the answer took when it was asked, saved on every hit
+    total_tokens: u64,
+    #[serde(default)]
+    hits: u64,
+}
+
+/// The answers cached on disk, None from `from_settings` when caching is off
+pub struct ResponseCache {
+    ttl: u64,
+    max_size: u64,
+}
+
+impl ResponseCache {
+    pub fn from_settings(settings: &Settings) -> Option<Self> {
+        if !settings.cache.unwrap_or(false) {
+            return None;
+        }
+        Some(Self {
+            ttl: settings.cache_ttl.unwrap_or(DEFAULT_TTL_HOURS) * SECONDS_PER_HOUR,
+            max_size: settings.cache_max_size.unwrap_or(DEFAULT_MAX_SIZE_MB) * 1024 * 1024,
+        })
+    }
+
+    /// The key of a request, the answer only depends on these
+    pub fn key(
+        provider: &str,
+        model: &str,
+        messages: &[Message],
+        params: &GenerationParams,
+    ) -> String {
+        json!({
+            "provider": provider,
+            "model": model,
+            "messages": messages,
+            "params": params,
+        })
+        .to_string()
+    }
+
+    /// Replays the cached answer as a stream, if there is a fresh one
+    pub fn replay(&self, key: &str) -> Option<EventStream> {
+        let path = entry_path(key);
+        let mut entry: Entry = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())?;
+        if entry.key != key {
+            return None;
+        }
+        if unix_now().saturating_sub(entry.created) >= self.ttl {
+            std::fs::remove_file(&path).ok();
+            return None;
+        }
+
+        entry.hits += 1;
+        if let Ok(content) = serde_json::to_string(&entry) {
+            std::fs::write(&path, content).ok();
+        }
+
+        // No usage is sent, a replay costs nothing
+        let events = pieces(&entry.reasoning)
+            .into_iter()
+            .map(StreamEvent::Reasoning)
+            .chain(pieces(&entry.answer).into_iter().map(StreamEvent::Delta));
+        Some(futures_util::stream::iter(events).boxed())
+    }
+
+    /// Passes the stream of a request on and caches the answer once it is
+    /// complete. Failed and cut off answers are not cached, nor stopped ones,
+    /// whose stream is dropped before it ends.
+    pub fn record(&self, key: String, mut events: EventStream) -> EventStream {
+        let max_size = self.max_size;
+
+        Box::pin(async_stream::stream! {
+            let mut reasoning = String::new();
+            let mut answer = String::new();
+            let mut usage: Option<Usage> = None;
+
+            while let Some(event) = events.next().await {
+                match &event {
+                    StreamEvent::Reasoning(piece) => reasoning.push_str(piece),
+                    StreamEvent::Delta(piece) => answer.push_str(piece),
+                    // The usage comes with the last chunk, without it the
+                    // answer may be cut off
+                    StreamEvent::Usage(reported) => usage = Some(*reported),
+                    StreamEvent::Error(_) => usage = None,
+                    _ => {}
+                }
+                let failed = matches!(event, StreamEvent::Error(_));
+                yield event;
+                if failed {
+                    return;
+                }
+            }
+
+            if answer.is_empty() {
+                return;
+            }
+            let Some(usage) = usage else {
+                return;
+            };
+            let entry = Entry {
+                created: unix_now(),
+                key,
+                reasoning,
+                answer,
+                total_tokens: usage.total_tokens,
+                hits: 0,
+            };
+            // A cache that can't be written only means the next question is asked again
+            store(&entry, max_size).ok();
+        })
+    }
+}
+
+/// Totals over the cached answers
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub entries: u64,
+    pub bytes: u64,
+    pub hits: u64,
+    /// Tokens the hits would have taken if asked again
+    pub saved_tokens: u64,
+}
+
+pub fn stats() -> Result<Stats> {
+    let mut stats = Stats::default();
+    for (path, bytes, _) in entries()? {
+        stats.entries += 1;
+        stats.bytes += bytes;
+        if let Some(entry) = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<Entry>(&content).ok())
+        {
+            stats.hits += entry.hits;
+            stats.saved_tokens += entry.hits * entry.total_tokens;
+        }
+    }
+    Ok(stats)
+}
+
+/// Removes every cached answer, returns how many there were
+pub fn clear() -> Result<u64> {
+    let mut removed = 0;
+    for (path, _, _) in entries()? {
+        std::fs::remove_file(path)?;
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+fn cache_dir() -> PathBuf {
+    Settings::config_dir().join(CACHE_DIR)
+}
+
+fn entry_path(key: &str) -> PathBuf {
+    let hash = fnv1a(key.as_bytes());
+    cache_dir().join(format!("{hash:016x}.json"))
+}
+
+/// The cache files with their size and modification time, oldest first
+fn entries() -> Result<Vec<(PathBuf, u64, SystemTime)>> {
+    let mut entries = Vec::new();
+    let dir = match std::fs::read_dir(cache_dir()) {
+        Ok(dir) => dir,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(entries),
+        Err(err) => return Err(err.into()),
+    };
+    for entry in dir {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_file() && entry.path().extension().is_some_and(|ext| ext == "json") {
+            entries.push((entry.path(), metadata.len(), metadata.modified()?));
+        }
+    }
+    entries.sort_by_key(|(_, _, modified)| *modified);
+    Ok(entries)
+}
+
+/// Writes an entry, then removes the least recently used ones until the
+/// cache is under its size limit
+fn store(entry: &Entry, max_size: u64) -> Result<()> {
+    std::fs::create_dir_all(cache_dir())?;
+    std::fs::write(entry_path(&entry.key), serde_json::to_string(entry)?)?;
+
+    let entries = entries()?;
+    let mut size: u64 = entries.iter().map(|(_, bytes, _)| bytes).sum();
+    for (path, bytes, _) in entries {
+        if size <= max_size {
+            break;
+        }
+        std::fs::remove_file(path)?;
+        size -= bytes;
+    }
+    Ok(())
+}
+
+/// Splits text into pieces at character boundaries
+fn pieces(text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    chars
+        .chunks(REPLAY_PIECE)
+        .map(|piece| piece.iter().collect())
+        .collect()
+}
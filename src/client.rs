@@ -0,0 +1,344 @@
+use futures_util::{Stream, StreamExt};
+use std::sync::mpsc::Receiver;
+
+use crate::ai::{
+    self, CancelHandle, EventStream, HttpOptions, Message, StreamEvent, ToolDefinition, Usage,
+};
+use crate::command::{AIProvider, GenerationParams};
+use crate::error::AskError;
+use crate::settings::Settings;
+
+/// A client for one model of one provider, made with [`Ask::builder`] or
+/// from the settings of the command line tool with [`Ask::from_settings`]
+#[derive(Debug, Clone)]
+pub struct Ask {
+    provider: AIProvider,
+    model: String,
+    api_key: String,
+    http: HttpOptions,
+    /// Used for the parameters a question doesn't set
+    params: GenerationParams,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AskBuilder {
+    provider: Option<AIProvider>,
+    model: Option<String>,
+    api_key: Option<String>,
+    http: HttpOptions,
+    params: GenerationParams,
+}
+
+impl AskBuilder {
+    pub fn provider(mut self, provider: AIProvider) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+
+    /// The model to ask, the first built-in model of the provider if not set
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    pub fn api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// The proxy, certificates and timeouts of the requests
+    pub fn http(mut self, http: HttpOptions) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Default generation parameters, used where a question doesn't set them
+    pub fn params(mut self, params: GenerationParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    pub fn build(self) -> Result<Ask, AskError> {
+        let provider = self.provider.ok_or_else(provider_not_set)?;
+        let model = self
+            .model
+            .or_else(|| provider.builtin_models().first().map(|m| m.to_string()))
+            .ok_or_else(model_not_set)?;
+        let api_key = self
+            .api_key
+            .ok_or_else(|| AskError::missing_key(provider))?;
+
+        Ok(Ask {
+            provider,
+            model,
+            api_key,
+            http: self.http,
+            params: self.params,
+        })
+    }
+}
+
+impl Ask {
+    pub fn builder() -> AskBuilder {
+        AskBuilder::default()
+    }
+
+    /// A client for the provider and model of the settings, with their API
+    /// key, network options and default parameters
+    pub fn from_settings(settings: &Settings) -> Result<Ask, AskError> {
+        let Some(provider) = settings.provider else {
+            return Err(provider_not_set());
+        };
+        let Some(model) = &settings.model else {
+            return Err(model_not_set());
+        };
+
+        // Any model is accepted, the provider rejects the ones it doesn't offer
+        let mut builder = Ask::builder()
+            .provider(provider)
+            .model(model)
+            .http(settings.http_options_for(provider))
+            .params(settings.params.clone().unwrap_or_default());
+        if let Some(api_key) = settings.api_key(provider) {
+            builder = builder.api_key(api_key);
+        }
+        builder.build()
+    }
+
+    pub fn provider(&self) -> AIProvider {
+        self.provider
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Starts streaming the answer to the messages, with exactly these
+    /// parameters. The events come from the receiver, the handle stops the
+    /// request.
+    pub fn start(
+        &self,
+        messages: &[Message],
+        params: &GenerationParams,
+        tools: &[ToolDefinition],
+    ) -> Result<(Receiver<StreamEvent>, CancelHandle), AskError> {
+        let (key, model, http) = (self.api_key.as_str(), self.model.as_str(), &self.http);
+        let started = match self.provider {
+            AIProvider::DeepSeek => ai::deepseek(messages, key, model, params, tools, http),
+            AIProvider::Grok => ai::grok(messages, key, model, params, tools, http),
+            AIProvider::Qwen => ai::qwen(messages, key, model, params, tools, http),
+        };
+        Ok(started?)
+    }
+
+    /// Like [`Ask::start`], for async code. Nothing is sent until the stream
+    /// is polled, and dropping it stops the request.
+    pub fn start_async(
+        &self,
+        messages: &[Message],
+        params: &GenerationParams,
+        tools: &[ToolDefinition],
+    ) -> Result<EventStream, AskError> {
+        let (key, model, http) = (self.api_key.as_str(), self.model.as_str(), &self.http);
+        let started = match self.provider {
+            AIProvider::DeepSeek => ai::deepseek_async(messages, key, model, params, tools, http),
+            AIProvider::Grok => ai::grok_async(messages, key, model, params, tools, http),
+            AIProvider::Qwen => ai::qwen_async(messages, key, model, params, tools, http),
+        };
+        Ok(started?)
+    }
+
+    /// The pieces of the answer to the question as they come in, for async
+    /// code. Dropping the stream stops the request.
+    pub fn stream_async(
+        &self,
+        question: &Question,
+    ) -> Result<impl Stream<Item = Result<Event, AskError>> + Send + 'static, AskError> {
+        let params = question.params.clone().or(&self.params);
+        let events = self.start_async(&question.messages, &params, &[])?;
+        Ok(events.filter_map(|event| std::future::ready(Event::from_stream(event))))
+    }
+
+    /// Asks the question and waits for the whole answer, for async code
+    pub async fn send_async(&self, question: &Question) -> Result<Answer, AskError> {
+        let mut events = std::pin::pin!(self.stream_async(question)?);
+        let mut answer = Answer::default();
+        while let Some(event) = events.next().await {
+            answer.push(event?);
+        }
+        Ok(answer)
+    }
+
+    /// Starts streaming the answer to the question
+    pub fn stream(&self, question: &Question) -> Result<AnswerStream, AskError> {
+        let params = question.params.clone().or(&self.params);
+        let (rx, cancel) = self.start(&question.messages, &params, &[])?;
+        Ok(AnswerStream { rx, cancel })
+    }
+
+    /// Asks the question and waits for the whole answer
+    pub fn send(&self, question: &Question) -> Result<Answer, AskError> {
+        self.stream(question)?.answer()
+    }
+
+    /// Asks the question, calling `on_event` for every piece of the answer as
+    /// it comes in, and returns the whole answer
+    pub fn send_with<F>(&self, question: &Question, mut on_event: F) -> Result<Answer, AskError>
+    where
+        F: FnMut(&Event),
+    {
+        let mut stream = self.stream(question)?;
+        let mut answer = Answer::default();
+        for event in &mut stream {
+            let event = event?;
+            on_event(&event);
+            answer.push(event);
+        }
+        stream.finish(answer)
+    }
+}
+
+fn provider_not_set() -> AskError {
+    AskError::config(
+        "AI provider is not set",
+        "Choose one with `ask config provider {deepseek|grok|qwen}`",
+    )
+}
+
+fn model_not_set() -> AskError {
+    AskError::config(
+        "AI model is not set",
+        "Choose one with `ask config model {model}`, see `ask models` for the models",
+    )
+}
+
+/// The messages sent to the model and the generation parameters
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Question {
+    pub messages: Vec<Message>,
+    /// Overrides the defaults of the client
+    pub params: GenerationParams,
+}
+
+impl Question {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self::from_messages(vec![Message::user(text)])
+    }
+
+    /// A question about images, given as http(s) or data URLs, see [`ai::image_url`]
+    pub fn with_images(text: impl Into<String>, image_urls: Vec<String>) -> Self {
+        Self::from_messages(vec![Message::user_with_images(text, image_urls)])
+    }
+
+    /// A whole conversation, the model answers the last message
+    pub fn from_messages(messages: Vec<Message>) -> Self {
+        Self {
+            messages,
+            params: GenerationParams::default(),
+        }
+    }
+
+    /// Sends a preset prompt before the question, the way ask sends presets
+    pub fn with_preset(mut self, prompt: impl Into<String>) -> Self {
+        self.messages.insert(0, Message::user(prompt));
+        self
+    }
+
+    pub fn with_params(mut self, params: GenerationParams) -> Self {
+        self.params = params;
+        self
+    }
+}
+
+/// A piece of a streamed answer
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// A new piece of the reasoning that reasoning models stream before the answer
+    Reasoning(String),
+    /// A new piece of the answer
+    Delta(String),
+    /// Token counts, once after the answer
+    Usage(Usage),
+}
+
+impl Event {
+    /// None for the events of tool calls, no tools are offered
+    fn from_stream(event: StreamEvent) -> Option<Result<Event, AskError>> {
+        let event = match event {
+            StreamEvent::Reasoning(text) => Event::Reasoning(text),
+            StreamEvent::Delta(text) => Event::Delta(text),
+            StreamEvent::Usage(usage) => Event::Usage(usage),
+            StreamEvent::Error(err) => return Some(Err(err)),
+            StreamEvent::ToolCalls(_) | StreamEvent::ToolRequest(_) => return None,
+        };
+        Some(Ok(event))
+    }
+}
+
+/// A complete answer
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Answer {
+    pub content: String,
+    /// Empty unless the model reasons before answering
+    pub reasoning: String,
+    /// None if the provider didn't report it
+    pub usage: Option<Usage>,
+}
+
+impl Answer {
+    fn push(&mut self, event: Event) {
+        match event {
+            Event::Reasoning(text) => self.reasoning.push_str(&text),
+            Event::Delta(text) => self.content.push_str(&text),
+            Event::Usage(usage) => *self.usage.get_or_insert_default() += usage,
+        }
+    }
+}
+
+/// The pieces of an answer as they come in. Dropping it stops the request.
+#[derive(Debug)]
+pub struct AnswerStream {
+    rx: Receiver<StreamEvent>,
+    cancel: CancelHandle,
+}
+
+impl AnswerStream {
+    /// Stops the request from another thread, the stream then ends
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
+
+    /// Waits for the rest of the answer
+    pub fn answer(mut self) -> Result<Answer, AskError> {
+        let mut answer = Answer::default();
+        for event in &mut self {
+            answer.push(event?);
+        }
+        self.finish(answer)
+    }
+
+    fn finish(&self, answer: Answer) -> Result<Answer, AskError> {
+        if self.cancel.is_cancelled() {
+            return Err(AskError::Cancelled);
+        }
+        Ok(answer)
+    }
+}
+
+impl Iterator for AnswerStream {
+    type Item = Result<Event, AskError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = Event::from_stream(self.rx.recv().ok()?) {
+                return Some(event);
+            }
+        }
+    }
+}
+
+impl Drop for AnswerStream {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
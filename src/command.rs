@@ -1,11 +1,18 @@
 #![allow(dead_code)]
 
+use std::ffi::OsStr;
 use std::fmt::Display;
+use std::path::PathBuf;
+use std::str::FromStr;
 
-use clap::{Args, Parser};
+use clap::builder::{NonEmptyStringValueParser, PossibleValue, TypedValueParser};
+use clap::{ArgAction, Args, Parser};
 use clap::{Subcommand, ValueEnum};
+use clap_complete::Shell;
 use serde::{Deserialize, Serialize};
 
+use crate::models;
+
 #[derive(ValueEnum, Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
 pub enum AIProvider {
     #[value(name = "deepseek")]
@@ -32,39 +39,125 @@ impl Display for AIProvider {
     }
 }
 
-#[derive(ValueEnum, Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
-pub enum AIModel {
-    #[value(name = "deepseek-chat")]
-    #[serde(rename = "deepseek-chat")]
-    DeepSeekChat,
+impl AIProvider {
+    /// The name of the provider in messages
+    pub fn name(&self) -> &'static str {
+        match self {
+            AIProvider::DeepSeek => "DeepSeek",
+            AIProvider::Grok => "Grok",
+            AIProvider::Qwen => "Qwen",
+        }
+    }
+
+    /// Models known to work, used until the provider's model list has been fetched
+    pub fn builtin_models(&self) -> &'static [&'static str] {
+        match self {
+            AIProvider::DeepSeek => &["deepseek-chat", "deepseek-reasoner"],
+            AIProvider::Grok => &["grok-3"],
+            AIProvider::Qwen => &["qwen-plus", "qwen-flash", "qwq-plus"],
+        }
+    }
+}
 
-    #[value(name = "grok-3")]
-    #[serde(rename = "grok-3")]
-    Grok3,
+/// Accepts any model id, so new models work without a new release.
+///
+/// The models known from the cache are offered as completions.
+#[derive(Clone, Debug)]
+pub struct ModelValueParser;
 
-    #[value(name = "qwen-plus")]
-    #[serde(rename = "qwen-plus")]
-    QwenPlus,
+impl TypedValueParser for ModelValueParser {
+    type Value = String;
 
-    #[value(name = "qwen-flash")]
-    #[serde(rename = "qwen-flash")]
-    QwenFlash,
+    fn parse_ref(
+        &self,
+        cmd: &clap::Command,
+        arg: Option<&clap::Arg>,
+        value: &OsStr,
+    ) -> Result<Self::Value, clap::Error> {
+        NonEmptyStringValueParser::new().parse_ref(cmd, arg, value)
+    }
+
+    fn possible_values(&self) -> Option<Box<dyn Iterator<Item = PossibleValue> + '_>> {
+        Some(Box::new(
+            models::known_models().into_iter().map(PossibleValue::new),
+        ))
+    }
 }
 
-impl Display for AIModel {
+/// How answers are shown
+#[derive(ValueEnum, Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Default)]
+pub enum OutputMode {
+    /// Full-screen answer view
+    #[default]
+    #[value(name = "tui")]
+    #[serde(rename = "tui")]
+    Tui,
+
+    /// Answer view drawn below the prompt, without the alternate screen
+    #[value(name = "inline")]
+    #[serde(rename = "inline")]
+    Inline,
+
+    /// Print the answer to stdout as it streams in
+    #[value(name = "plain")]
+    #[serde(rename = "plain")]
+    Plain,
+}
+
+impl Display for OutputMode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let name = self.name();
+        let name = match self {
+            OutputMode::Tui => "tui",
+            OutputMode::Inline => "inline",
+            OutputMode::Plain => "plain",
+        };
         write!(f, "{}", name)
     }
 }
 
-impl AIModel {
-    pub fn name(&self) -> &str {
-        match self {
-            AIModel::DeepSeekChat => "deepseek-chat",
-            AIModel::Grok3 => "grok-3",
-            AIModel::QwenPlus => "qwen-plus",
-            AIModel::QwenFlash => "qwen-flash",
+/// Generation parameters passed through to the provider. Unset ones are left
+/// to the provider's defaults.
+#[derive(Args, Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct GenerationParams {
+    /// Sampling temperature, higher values give more random answers
+    #[arg(short = 't', long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    /// Only sample from the most likely tokens within this probability mass
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    /// Maximum number of tokens to generate
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u64>,
+    /// Seed for repeatable sampling, if the provider supports it
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// Stop generating at this sequence, can be given several times
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+}
+
+impl GenerationParams {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Fills the parameters that are not set from `fallback`
+    pub fn or(self, fallback: &GenerationParams) -> GenerationParams {
+        GenerationParams {
+            temperature: self.temperature.or(fallback.temperature),
+            top_p: self.top_p.or(fallback.top_p),
+            max_tokens: self.max_tokens.or(fallback.max_tokens),
+            seed: self.seed.or(fallback.seed),
+            stop: if self.stop.is_empty() {
+                fallback.stop.clone()
+            } else {
+                self.stop
+            },
         }
     }
 }
@@ -75,7 +168,18 @@ impl AIModel {
 ask is a command-line tool that makes it easier to get quick answers to simple questions compared to using a web browser.
 
 - Ask directly - ask {question}
-- Use preset - ask {preset} {question}"#,
+- Use preset - ask {preset} {question}, or explicitly - ask -P {preset} {question}
+- Compare models side by side - ask --compare deepseek:deepseek-chat,qwen:qwen-plus {question}
+- Print the reply as JSON lines - ask --jsonl {question}
+- Ask about an image with a vision model - ask --image screenshot.png {question}
+- Let the model use local tools, after confirming each call - ask --tools {question}
+- Answer from a directory of documents, with citations - ask --docs ./runbooks {question}
+- Ask again instead of reusing a cached answer - ask --refresh {question}, or without the cache - ask --no-cache {question}
+- Ask a question that starts like a subcommand or a flag - ask -- {question}
+- Use another model once - ask -m qwen:qwen-plus {question}, or another provider - ask -p qwen {question}
+- Keep the answer on screen after exit - ask --keep {question}
+- Print the reasoning of reasoning models in plain output - ask --show-reasoning {question}
+- Set generation parameters - ask -t 0.2 --max-tokens 500 --top-p 0.9 --seed 42 --stop END {question}"#,
     after_help = r#"
 Environment Variables for API Keys
 
@@ -83,20 +187,152 @@ Environment Variables for API Keys
   - Grok - export ASK_GROK_KEY={your key}
   - Qwen - export ASK_QWEN_KEY={your key}
 
+  Another OpenAI-compatible URL, e.g. a gateway: export ASK_DEEPSEEK_BASE_URL={url},
+  likewise ASK_GROK_BASE_URL and ASK_QWEN_BASE_URL
+
 Examples:
 
   ask config provider deepseek
   ask config model deepseek-chat
-  ask config timeout 60
+  ask config timeout 60 --first-token 60 --idle 30
+  ask config output plain
+  ask config keep-answer true
+  ask config price deepseek-chat 0.27 1.1
+  ask config params --temperature 0.7 --max-tokens 2048
+  ask config tool-commands ls cat "git log" "cargo check"
+  ask config cache true --ttl 168 --max-size 100
+  ask config network --proxy http://proxy.corp:8080 --ca-bundle ~/corp-ca.pem
 
   ask hello
   ask preset set rust You are a Rust programming expert. Answer questions about Rust programming.
   ask rust Tell me about the Ownership system in Rust.
+  ask preset set translate -t 0 Translate the following text into English.
+  ask -t 1.2 write a haiku about autumn
+  ask --tools what does src/main.rs do?
+  ask --docs ./runbooks how do I rotate the database password?
+
+  ask usage --days 7
+  ask models --provider qwen
+  ask cache stats
+  ask serve --port 8787
+  ask completions bash > ~/.local/share/bash-completion/completions/ask
 "#
 )]
 pub struct AskArgsParser {
     #[command(subcommand)]
-    pub command: AskCommand,
+    pub command: Option<AskCommand>,
+    #[command(flatten)]
+    pub question: QuestionArgs,
+}
+
+/// Parses the arguments as a question only, for questions that start with
+/// the name of a subcommand, e.g. `ask config is what in nginx?`
+#[derive(Parser, Debug)]
+#[command(name = "ask")]
+pub struct QuestionParser {
+    #[command(flatten)]
+    pub question: QuestionArgs,
+}
+
+/// Options for a question, given before the question
+#[derive(Args, Debug, Clone, Default)]
+pub struct QuestionArgs {
+    /// Use this preset, instead of the first word of the question if it names one
+    #[arg(short = 'P', long)]
+    pub preset: Option<String>,
+    /// Use another model for this question only, as provider:model or model
+    #[arg(short, long, value_name = "PROVIDER:MODEL")]
+    pub model: Option<ModelChoice>,
+    /// Use another provider for this question only
+    #[arg(short, long, value_enum)]
+    pub provider: Option<AIProvider>,
+    /// Ask several models at once and show their answers side by side
+    #[arg(
+        long,
+        value_name = "PROVIDER:MODEL,...",
+        value_delimiter = ',',
+        conflicts_with_all = ["model", "provider"]
+    )]
+    pub compare: Vec<ModelChoice>,
+    /// Send an image file or URL with the question, for vision models. Can be
+    /// given several times
+    #[arg(short, long = "image", value_name = "PATH|URL")]
+    pub images: Vec<String>,
+    /// Print the reply as JSON lines, one event per line, tagged with the model
+    #[arg(long)]
+    pub jsonl: bool,
+    /// Let the model read files, list directories, grep, run whitelisted
+    /// commands and fetch URLs, each call after your confirmation
+    #[arg(long, conflicts_with = "compare")]
+    pub tools: bool,
+    /// Answer from the files in a directory, the best matching excerpts are
+    /// sent along with the question and cited
+    #[arg(long, value_name = "DIR")]
+    pub docs: Option<PathBuf>,
+    /// Also rank the excerpts of --docs by embeddings, which needs a provider
+    /// offering them (Qwen)
+    #[arg(long, requires = "docs")]
+    pub embeddings: bool,
+    /// Neither use nor store a cached answer
+    #[arg(long, conflicts_with = "refresh")]
+    pub no_cache: bool,
+    /// Ask again even if the answer is cached, and cache the new answer
+    #[arg(long)]
+    pub refresh: bool,
+    /// Print the answer to the normal screen after leaving the answer view
+    #[arg(long = "keep")]
+    pub keep_answer: bool,
+    /// Include the reasoning of reasoning models in plain output
+    #[arg(long)]
+    pub show_reasoning: bool,
+    #[command(flatten)]
+    pub params: GenerationParams,
+    /// The question, put `--` before it if it starts like a subcommand or a flag
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    pub question: Vec<String>,
+    /// Set when the question follows `--`, it is then taken literally
+    #[arg(skip)]
+    pub literal: bool,
+}
+
+/// A model with an optional provider, e.g. `qwen:qwen-plus` or `qwen-plus`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelChoice {
+    pub provider: Option<AIProvider>,
+    pub model: String,
+}
+
+impl FromStr for ModelChoice {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        // The provider prefix is optional, and model ids may contain colons
+        let prefixed = value.split_once(':').and_then(|(provider, model)| {
+            Some((AIProvider::from_str(provider, true).ok()?, model))
+        });
+        let (provider, model) = match prefixed {
+            Some((provider, model)) => (Some(provider), model),
+            None => (None, value),
+        };
+
+        if model.is_empty() {
+            return Err("the model is missing".to_owned());
+        }
+
+        Ok(ModelChoice {
+            provider,
+            model: model.to_owned(),
+        })
+    }
+}
+
+impl Display for ModelChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.provider {
+            Some(provider) => write!(f, "{provider}:{}", self.model),
+            None => write!(f, "{}", self.model),
+        }
+    }
 }
 
 #[derive(Subcommand, Debug)]
@@ -105,6 +341,16 @@ pub enum AskCommand {
     Config(ConfigCommand),
     /// Manage AI presets
     Preset(PresetCommand),
+    /// Summarise token usage and estimated cost
+    Usage(UsageArgs),
+    /// List the models available from the providers
+    Models(ModelsArgs),
+    /// Print a shell completion script
+    Completions(CompletionsArgs),
+    /// Manage the cache of answers
+    Cache(CacheCommand),
+    /// Serve an OpenAI-compatible API for editors and other local tools
+    Serve(ServeArgs),
 }
 
 // Configuration management commands
@@ -121,10 +367,26 @@ pub enum ConfigSubcommand {
     Show,
     /// Set AI provider to use
     Provider(ConfigProviderArgs),
-    /// Set timeout for requests
+    /// Set the timeouts for requests
     Timeout(ConfigTimeoutArgs),
     /// Set AI model to use
     Model(ConfigModelArgs),
+    /// Set how answers are shown
+    Output(ConfigOutputArgs),
+    /// Print the answer to the normal screen after leaving the answer view
+    KeepAnswer(ConfigKeepAnswerArgs),
+    /// Set the price of a model, used to estimate costs
+    Price(ConfigPriceArgs),
+    /// Set the default generation parameters
+    Params(ConfigParamsArgs),
+    /// Set the commands the model may run with --tools
+    ToolCommands(ConfigToolCommandsArgs),
+    /// Set the models `ask serve` answers with when the provider fails
+    Fallback(ConfigFallbackArgs),
+    /// Cache answers to repeated questions
+    Cache(ConfigCacheArgs),
+    /// Set the proxy, certificates and connect timeout for requests
+    Network(ConfigNetworkArgs),
 }
 
 #[derive(Args, Debug)]
@@ -134,15 +396,123 @@ pub struct ConfigProviderArgs {
 }
 
 #[derive(Args, Debug)]
+#[command(arg_required_else_help = true)]
 pub struct ConfigTimeoutArgs {
-    #[arg(value_enum, help = "Set the request timeout in seconds")]
-    pub timeout: u64,
+    #[arg(help = "Set the timeout in seconds of requests that aren't streamed, e.g. `ask models`")]
+    pub timeout: Option<u64>,
+    #[arg(long, help = "Set how long an answer may take to start, in seconds")]
+    pub first_token: Option<u64>,
+    #[arg(
+        long,
+        help = "Set how long an answer may pause while streaming, in seconds"
+    )]
+    pub idle: Option<u64>,
 }
 
 #[derive(Args, Debug)]
 pub struct ConfigModelArgs {
-    #[arg(value_enum, help = "Select the AI model to use")]
-    pub model: AIModel,
+    #[arg(
+        value_parser = ModelValueParser,
+        hide_possible_values = true,
+        help = "Select the AI model to use, see `ask models`"
+    )]
+    pub model: String,
+}
+
+#[derive(Args, Debug)]
+pub struct ConfigOutputArgs {
+    #[arg(value_enum, help = "Select how answers are shown")]
+    pub output: OutputMode,
+}
+
+#[derive(Args, Debug)]
+pub struct ConfigKeepAnswerArgs {
+    #[arg(action = ArgAction::Set, help = "Whether to keep the answer on screen")]
+    pub keep: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ConfigPriceArgs {
+    #[arg(
+        value_parser = ModelValueParser,
+        hide_possible_values = true,
+        help = "The AI model the price is for"
+    )]
+    pub model: String,
+    #[arg(help = "Price per million prompt tokens")]
+    pub input: f64,
+    #[arg(help = "Price per million completion tokens")]
+    pub output: f64,
+}
+
+#[derive(Args, Debug)]
+pub struct ConfigParamsArgs {
+    #[command(flatten)]
+    pub params: GenerationParams,
+    #[arg(long, help = "Remove all default generation parameters first")]
+    pub reset: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ConfigCacheArgs {
+    #[arg(action = ArgAction::Set, help = "Whether to cache answers")]
+    pub enabled: bool,
+    #[arg(long, help = "How long answers are reused, in hours")]
+    pub ttl: Option<u64>,
+    #[arg(
+        long,
+        value_name = "MB",
+        help = "Size the cache is kept under, in megabytes"
+    )]
+    pub max_size: Option<u64>,
+}
+
+#[derive(Args, Debug)]
+pub struct ConfigNetworkArgs {
+    #[arg(
+        long,
+        help = "Proxy URL all requests go through, e.g. http://proxy.corp:8080"
+    )]
+    pub proxy: Option<String>,
+    #[arg(
+        long,
+        help = "Comma-separated hosts that are not sent through the proxy"
+    )]
+    pub no_proxy: Option<String>,
+    #[arg(long, help = "PEM file with extra certificates to trust")]
+    pub ca_bundle: Option<PathBuf>,
+    #[arg(
+        long,
+        action = ArgAction::Set,
+        help = "Accept invalid TLS certificates, only for debugging"
+    )]
+    pub insecure_skip_verify: Option<bool>,
+    #[arg(long, help = "Set the connect timeout in seconds")]
+    pub connect_timeout: Option<u64>,
+    #[arg(long, help = "Remove all network settings first")]
+    pub reset: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ConfigToolCommandsArgs {
+    #[arg(
+        help = "Command lines that may be run, matched by their leading words, e.g. ls \"git log\""
+    )]
+    pub commands: Vec<String>,
+    #[arg(long, help = "Go back to the built-in commands")]
+    pub reset: bool,
+}
+
+#[derive(Args, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct ConfigFallbackArgs {
+    #[arg(
+        value_name = "PROVIDER:MODEL",
+        help = "Models tried in order, e.g. qwen:qwen-plus grok:grok-3"
+    )]
+    pub models: Vec<ModelChoice>,
+    #[arg(long, conflicts_with = "models", help = "Remove the fallback models")]
+    pub reset: bool,
 }
 
 // #region Preset management commands
@@ -167,6 +537,9 @@ pub enum PresetSubcommand {
 pub struct PresetSetArgs {
     /// Name of the preset
     pub name: String,
+    /// Generation parameters used with this preset, overriding the defaults
+    #[command(flatten)]
+    pub params: GenerationParams,
     /// Prompt for the preset
     pub prompt: Vec<String>,
 }
@@ -178,3 +551,105 @@ pub struct PresetRemoveArgs {
 }
 
 // #endregion
+
+// #region Usage reporting
+
+#[derive(Args, Debug)]
+pub struct UsageArgs {
+    /// Only include requests from the last N days
+    #[arg(long, default_value_t = 30)]
+    pub days: u64,
+}
+
+// #endregion
+
+// #region Models and completions
+
+#[derive(Args, Debug)]
+pub struct ModelsArgs {
+    /// Only list the models of this provider
+    #[arg(long, value_enum)]
+    pub provider: Option<AIProvider>,
+    /// Fetch the model lists even if the cached ones are recent
+    #[arg(long)]
+    pub refresh: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    #[arg(long, default_value_t = 8787, help = "Port to listen on")]
+    pub port: u16,
+    #[arg(
+        long,
+        default_value = "127.0.0.1",
+        help = "Address to listen on, other machines can use the API keys if it isn't local"
+    )]
+    pub host: String,
+}
+
+#[derive(Args, Debug)]
+pub struct CompletionsArgs {
+    /// Shell to generate the completion script for
+    #[arg(value_enum)]
+    pub shell: Shell,
+}
+
+// #endregion
+
+// #region Answer cache
+
+#[derive(Args, Debug)]
+pub struct CacheCommand {
+    #[command(subcommand)]
+    pub command: CacheSubcommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CacheSubcommand {
+    /// Show the number of cached answers, their size and the hits
+    Stats,
+    /// Remove all cached answers
+    Clear,
+}
+
+// #endregion
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    fn choice(value: &str) -> Result<(Option<AIProvider>, String), String> {
+        ModelChoice::from_str(value).map(|choice| (choice.provider, choice.model))
+    }
+
+    #[test]
+    fn model_choices() {
+        let model = |model: &str| model.to_owned();
+        assert_eq!(
+            choice("qwen:qwen-plus"),
+            Ok((Some(AIProvider::Qwen), model("qwen-plus")))
+        );
+        assert_eq!(choice("qwen-plus"), Ok((None, model("qwen-plus"))));
+        assert_eq!(
+            choice("DeepSeek:deepseek-chat"),
+            Ok((Some(AIProvider::DeepSeek), model("deepseek-chat")))
+        );
+        // Only the first colon can end a provider
+        assert_eq!(
+            choice("qwen:qwen3:32b"),
+            Ok((Some(AIProvider::Qwen), model("qwen3:32b")))
+        );
+        // An unknown prefix is part of the model id
+        assert_eq!(choice("llama3:8b"), Ok((None, model("llama3:8b"))));
+        assert_eq!(choice("openai:gpt-4o"), Ok((None, model("openai:gpt-4o"))));
+        assert!(choice("grok:").is_err());
+        assert!(choice("").is_err());
+    }
+
+    #[test]
+    fn parsers_are_valid() {
+        AskArgsParser::command().debug_assert();
+        QuestionParser::command().debug_assert();
+    }
+}
@@ -0,0 +1,388 @@
+use anyhow::Result;
+use ask::AskError;
+use ratatui::DefaultTerminal;
+use ratatui::Frame;
+use ratatui::crossterm::event::{
+    DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers,
+};
+use ratatui::crossterm::{execute, terminal};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::{Line, Span, Text};
+use ratatui::widgets::{Block, Paragraph};
+use std::io::stdout;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+
+use crate::ai::{CancelHandle, StreamEvent, Usage};
+use crate::markdown;
+use crate::settings::ModelPrice;
+use crate::tui::{self, Scroll, Status};
+
+/// A reply to compare, streamed into its own column
+pub struct Stream {
+    /// Names the model, e.g. "qwen:qwen-plus"
+    pub label: String,
+    pub rx: Receiver<StreamEvent>,
+    pub cancel: CancelHandle,
+    /// Price of the model, to show the estimated cost with the token usage
+    pub price: Option<ModelPrice>,
+}
+
+struct Column {
+    label: String,
+    rx: Option<Receiver<StreamEvent>>,
+    cancel: CancelHandle,
+    price: Option<ModelPrice>,
+    answer: String,
+    /// Set once reasoning arrives, which is not shown in the columns
+    reasoning: bool,
+    status: Status,
+    /// Time to the first piece of reasoning or answer
+    first_token: Option<Duration>,
+    /// Time the request took, set once it is no longer in flight
+    elapsed: Option<Duration>,
+    usage: Option<Usage>,
+    /// The error the request failed with
+    failure: Option<AskError>,
+    scroll: Scroll,
+    /// The answer as last rendered, see `Rendered`
+    rendered: Option<Rendered>,
+}
+
+/// A rendered answer and its wrapped height, rendered again only once more
+/// of the answer arrives or the width changes
+struct Rendered {
+    answer_len: usize,
+    width: u16,
+    text: Text<'static>,
+    line_count: usize,
+}
+
+impl Column {
+    fn new(stream: Stream) -> Self {
+        Self {
+            label: stream.label,
+            rx: Some(stream.rx),
+            cancel: stream.cancel,
+            price: stream.price,
+            answer: String::new(),
+            reasoning: false,
+            status: Status::Waiting,
+            first_token: None,
+            elapsed: None,
+            usage: None,
+            failure: None,
+            scroll: Scroll::default(),
+            rendered: None,
+        }
+    }
+
+    fn in_flight(&self) -> bool {
+        matches!(self.status, Status::Waiting | Status::Streaming)
+    }
+
+    fn finish(&mut self, status: Status, started: Instant) {
+        self.status = status;
+        self.elapsed = Some(started.elapsed());
+    }
+
+    /// Drains everything that arrived since the last frame
+    fn poll(&mut self, started: Instant) {
+        while let Some(rx) = &self.rx {
+            match rx.try_recv() {
+                Ok(event) => self.on_stream_event(event, started),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    if self.in_flight() {
+                        self.finish(Status::Done, started);
+                    }
+                    self.rx = None;
+                }
+            }
+        }
+
+        if !self.in_flight() && self.rx.is_some() {
+            self.cancel.cancel();
+            self.rx = None;
+        }
+    }
+
+    fn on_stream_event(&mut self, event: StreamEvent, started: Instant) {
+        match event {
+            StreamEvent::Usage(usage) => self.usage = Some(usage),
+            StreamEvent::Reasoning(_) | StreamEvent::Delta(_) if self.first_token.is_none() => {
+                self.first_token = Some(started.elapsed());
+                self.on_stream_event(event, started);
+            }
+            StreamEvent::Reasoning(_) => {
+                self.status = Status::Streaming;
+                self.reasoning = true;
+            }
+            StreamEvent::Delta(content) => {
+                self.status = Status::Streaming;
+                self.answer.push_str(&content);
+            }
+            // Tools are not offered when comparing
+            StreamEvent::ToolCalls(_) | StreamEvent::ToolRequest(_) => {}
+            StreamEvent::Error(err) => {
+                self.finish(Status::Failed(format!("{err:#}")), started);
+                self.failure = Some(err);
+            }
+        }
+    }
+
+    fn rendered(&mut self, width: u16) -> &Rendered {
+        let stale = self.rendered.as_ref().is_none_or(|rendered| {
+            rendered.answer_len != self.answer.len() || rendered.width != width
+        });
+        if stale {
+            let text = markdown::render(&self.answer, None);
+            self.rendered = Some(Rendered {
+                answer_len: self.answer.len(),
+                width,
+                line_count: tui::wrapped_height(text.clone(), width),
+                text,
+            });
+        }
+        self.rendered.as_ref().unwrap()
+    }
+
+    /// Latency and token stats shown above the answer
+    fn stats(&self, started: Instant) -> Line<'static> {
+        let elapsed = self.elapsed.unwrap_or_else(|| started.elapsed());
+        let elapsed = format!("{:.1}s", elapsed.as_secs_f64());
+        let frame = tui::spinner_frame(started);
+
+        let status = match &self.status {
+            Status::Waiting => Span::from(format!("{frame} Waiting {elapsed}")).yellow(),
+            Status::Streaming if self.answer.is_empty() && self.reasoning => {
+                Span::from(format!("{frame} Thinking {elapsed}")).cyan()
+            }
+            Status::Streaming => Span::from(format!("{frame} Generating {elapsed}")).cyan(),
+            Status::Done => Span::from(format!("✔ {elapsed}")).green(),
+            Status::Failed(err) => Span::from(format!("✘ {elapsed}: {err}")).red(),
+            Status::Stopped => Span::from(format!("■ {elapsed}")).red(),
+        };
+
+        let mut spans = vec![status.bold()];
+        if let Some(first_token) = self.first_token {
+            spans.push(Span::from(format!(
+                ", first token {:.1}s",
+                first_token.as_secs_f64()
+            )));
+        }
+        if let Some(usage) = self.usage {
+            let mut tokens = format!(", {} tokens", usage.total_tokens);
+            if let Some(price) = self.price {
+                let cost = price.cost(usage.prompt_tokens, usage.completion_tokens);
+                tokens.push_str(&format!(" ≈ {cost:.6}"));
+            }
+            spans.push(Span::from(tokens).green());
+        }
+
+        Line::from(spans)
+    }
+}
+
+struct CompareApp<'a> {
+    question: &'a str,
+    columns: Vec<Column>,
+    /// Column that scrolling and copying apply to
+    focused: usize,
+    started: Instant,
+    /// Short-lived message shown in the status line
+    notice: Option<(String, Instant)>,
+    quit: bool,
+    /// Left with Ctrl-C while answers were still streaming
+    cancelled: bool,
+}
+
+impl CompareApp<'_> {
+    fn on_key(&mut self, key: KeyEvent) {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            // Like an interrupted command, unlike leaving with q
+            self.cancelled = self.columns.iter().any(Column::in_flight);
+            self.quit = true;
+            return;
+        }
+
+        let count = self.columns.len();
+        match key.code {
+            KeyCode::Char('q') => self.quit = true,
+            KeyCode::Esc | KeyCode::Char('s') => self.stop(),
+            KeyCode::Tab | KeyCode::Right | KeyCode::Char('l') => {
+                self.focused = (self.focused + 1) % count;
+            }
+            KeyCode::BackTab | KeyCode::Left | KeyCode::Char('h') => {
+                self.focused = (self.focused + count - 1) % count;
+            }
+            KeyCode::Char('y') => self.copy_answer(),
+            code => self.columns[self.focused].scroll.on_key(code),
+        }
+    }
+
+    /// Stops every request that is still generating, keeping the partial answers
+    fn stop(&mut self) {
+        for column in &mut self.columns {
+            if column.in_flight() {
+                column.finish(Status::Stopped, self.started);
+            }
+        }
+    }
+
+    fn copy_answer(&mut self) {
+        let column = &self.columns[self.focused];
+        let notice = if column.answer.is_empty() {
+            "Nothing to copy yet".to_owned()
+        } else {
+            match tui::copy_to_clipboard(&column.answer) {
+                Ok(()) => format!("Copied the answer of {}", column.label),
+                Err(err) => format!("Failed to copy the answer: {err}"),
+            }
+        };
+        self.notice = Some((notice, Instant::now()));
+    }
+
+    fn status_line(&self) -> Line<'static> {
+        let answering = self.columns.iter().filter(|c| c.in_flight()).count();
+        let elapsed = format!("{:.1}s", self.started.elapsed().as_secs_f64());
+
+        let status = if answering > 0 {
+            Span::from(format!(
+                "{} {answering} of {} answering {elapsed}",
+                tui::spinner_frame(self.started),
+                self.columns.len()
+            ))
+            .cyan()
+        } else {
+            Span::from("✔ All done").green()
+        };
+        let hint = match &self.notice {
+            Some((notice, at)) if at.elapsed() < tui::NOTICE_DURATION => {
+                Span::from(format!("  {notice}")).magenta()
+            }
+            _ => Span::from("  [q quit, s stop, Tab/←→ column, j/k scroll, y copy]").dark_gray(),
+        };
+
+        Line::from(vec![status.bold(), hint])
+    }
+
+    fn render(&mut self, f: &mut Frame) {
+        let [status_area, question_area, columns_area] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Fill(1),
+        ])
+        .margin(1)
+        .areas(f.area());
+
+        f.render_widget(Paragraph::new(self.status_line()), status_area);
+        f.render_widget(
+            Paragraph::new(Line::from(self.question.to_owned()).dark_gray()),
+            question_area,
+        );
+
+        let count = self.columns.len() as u32;
+        let areas =
+            Layout::horizontal((0..count).map(|_| Constraint::Ratio(1, count))).split(columns_area);
+
+        for (index, (column, area)) in self.columns.iter_mut().zip(areas.iter()).enumerate() {
+            let border = if index == self.focused {
+                Style::new().cyan()
+            } else {
+                Style::new().dark_gray()
+            };
+            let block = Block::bordered()
+                .title(Line::from(column.label.clone()).bold())
+                .border_style(border);
+            let inner = block.inner(*area);
+            f.render_widget(block, *area);
+
+            let [stats_area, answer_area] =
+                Layout::vertical([Constraint::Length(1), Constraint::Fill(1)])
+                    .spacing(1)
+                    .areas(inner);
+            f.render_widget(Paragraph::new(column.stats(self.started)), stats_area);
+
+            let rendered = column.rendered(answer_area.width);
+            let (text, line_count) = (rendered.text.clone(), rendered.line_count);
+            column.scroll.fit(line_count, answer_area.height);
+            f.render_widget(column.scroll.paragraph(text), answer_area);
+        }
+    }
+}
+
+/// Shows the streamed replies side by side until the user quits.
+///
+/// Returns the token usage of each reply, in the order of the streams, or the
+/// error of the first reply if every one failed. With `keep_answer` the
+/// answers are printed to the normal screen afterwards.
+pub fn handle_replies(
+    question: &str,
+    streams: Vec<Stream>,
+    keep_answer: bool,
+) -> Result<Vec<Option<Usage>>> {
+    let mut app = CompareApp {
+        question,
+        columns: streams.into_iter().map(Column::new).collect(),
+        focused: 0,
+        started: Instant::now(),
+        notice: None,
+        quit: false,
+        cancelled: false,
+    };
+
+    let mut terminal = ratatui::init();
+    execute!(stdout(), EnableMouseCapture)?;
+    let result = run(&mut terminal, &mut app);
+    execute!(stdout(), DisableMouseCapture)?;
+    ratatui::restore();
+
+    // Leaving the view aborts the requests that are still streaming
+    for column in &app.columns {
+        column.cancel.cancel();
+    }
+    result?;
+
+    let (width, _) = terminal::size()?;
+    for column in &app.columns {
+        if keep_answer && !column.answer.is_empty() {
+            println!("\x1b[1m## {}\x1b[0m\n", column.label);
+            tui::print_text(markdown::render(&column.answer, None), width)?;
+            println!();
+        }
+        if let Status::Failed(err) = &column.status {
+            eprintln!("{}: {err}", column.label);
+        }
+    }
+
+    if app.cancelled {
+        return Err(AskError::Cancelled.into());
+    }
+    let usages = app.columns.iter().map(|column| column.usage).collect();
+    if app.columns.iter().all(|column| column.failure.is_some())
+        && let Some(err) = app.columns.into_iter().find_map(|column| column.failure)
+    {
+        return Err(err.into());
+    }
+    Ok(usages)
+}
+
+fn run(terminal: &mut DefaultTerminal, app: &mut CompareApp) -> Result<()> {
+    while !app.quit {
+        for column in &mut app.columns {
+            column.poll(app.started);
+        }
+
+        terminal.draw(|f| app.render(f))?;
+
+        match tui::next_event()? {
+            Some(Event::Key(key)) if key.kind == KeyEventKind::Press => app.on_key(key),
+            Some(Event::Mouse(mouse)) => app.columns[app.focused].scroll.on_mouse(mouse),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
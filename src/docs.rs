@@ -0,0 +1,616 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::ai;
+use crate::command::AIProvider;
+use crate::settings::Settings;
+use crate::util::fnv1a;
+
+/// Directory in the config directory holding one index per indexed directory
+static INDEX_DIR: &str = "ask_docs";
+/// Bumped whenever the format or the chunking changes, older indexes are rebuilt
+static INDEX_VERSION: u32 = 2;
+/// Chunks are cut at the first line break after this many bytes
+static CHUNK_SIZE: usize = 1500;
+/// Lines a chunk repeats from the end of the previous one, so text cut in two
+/// is still found
+static CHUNK_OVERLAP: usize = 2;
+/// Larger files are mostly generated or data, they are skipped
+static MAX_FILE_SIZE: u64 = 1024 * 1024;
+/// Chunks sent along with the question
+static TOP_CHUNKS: usize = 5;
+/// BM25 parameters, the usual defaults
+static BM25_K1: f64 = 1.2;
+static BM25_B: f64 = 0.75;
+/// Reciprocal rank fusion constant for combining BM25 and embedding ranks
+static RRF_K: f64 = 60.0;
+
+/// Extensions of the files that are indexed
+static EXTENSIONS: &[&str] = &[
+    "md", "markdown", "mdx", "txt", "rst", "adoc", "org", "rs", "py", "js", "jsx", "ts", "tsx",
+    "go", "java", "kt", "swift", "c", "h", "cc", "cpp", "hpp", "cs", "rb", "php", "lua", "sh",
+    "bash", "zsh", "ps1", "sql", "toml", "yaml", "yml", "json", "ini", "conf", "cfg", "tf", "html",
+    "css", "scss", "vue", "svelte",
+];
+/// Directories that are never indexed, besides hidden ones
+static SKIPPED_DIRS: &[&str] = &["target", "node_modules", "vendor", "dist", "build"];
+
+/// The index of one directory, kept in the config directory
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    version: u32,
+    /// Model the chunk embeddings come from, they are dropped when it changes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    embedding_model: Option<String>,
+    /// Files by path relative to the indexed directory
+    files: BTreeMap<String, IndexedFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct IndexedFile {
+    /// Modification time in milliseconds since the Unix epoch
+    mtime: u64,
+    size: u64,
+    chunks: Vec<Chunk>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Chunk {
+    /// First and last line, counted from 1
+    start_line: usize,
+    end_line: usize,
+    text: String,
+    /// Term frequencies for BM25
+    terms: HashMap<String, u32>,
+    length: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    embedding: Option<Vec<f32>>,
+}
+
+/// A chunk retrieved for a question
+#[derive(Debug, Clone)]
+pub struct Excerpt {
+    /// The file, as the directory was given plus the path within it
+    pub path: String,
+    pub start_line: usize,
+    pub end_line: usize,
+    pub text: String,
+}
+
+impl Excerpt {
+    /// Where the excerpt comes from, e.g. `runbooks/db.md:10-42`
+    pub fn source(&self) -> String {
+        format!("{}:{}-{}", self.path, self.start_line, self.end_line)
+    }
+}
+
+/// How the chunks are embedded, if they are
+pub struct Embedder<'a> {
+    pub provider: AIProvider,
+    pub api_key: &'a str,
+    pub http: ai::HttpOptions,
+}
+
+/// Brings the index of the directory up to date and returns the chunks that
+/// best match the question, best first
+pub fn retrieve(dir: &Path, question: &str, embedder: Option<&Embedder>) -> Result<Vec<Excerpt>> {
+    let root = dir
+        .canonicalize()
+        .with_context(|| format!("Cannot access {}", dir.display()))?;
+    if !root.is_dir() {
+        return Err(anyhow::anyhow!("{} is not a directory", dir.display()));
+    }
+
+    let index_path = index_path(&root);
+    let mut index = load_index(&index_path);
+    let changed = update(&mut index, &root)?;
+    let embedded = match embedder {
+        Some(embedder) => embed_chunks(&mut index, embedder)?,
+        None => 0,
+    };
+    if changed > 0 || embedded > 0 {
+        save_index(&index_path, &index)?;
+    }
+
+    let query_terms: Vec<String> = tokenize(question).into_keys().collect();
+    let mut ranked = rank_bm25(&index, &query_terms);
+    if let Some(embedder) = embedder {
+        let query = ai::embeddings(
+            embedder.provider,
+            embedder.api_key,
+            &[question.to_owned()],
+            &embedder.http,
+        )?
+        .pop()
+        .unwrap_or_default();
+        ranked = fuse(&ranked, &rank_embeddings(&index, &query));
+    }
+
+    let chunks: Vec<(&String, &Chunk)> = index
+        .files
+        .iter()
+        .flat_map(|(path, file)| file.chunks.iter().map(move |chunk| (path, chunk)))
+        .collect();
+
+    Ok(ranked
+        .into_iter()
+        .take(TOP_CHUNKS)
+        .map(|(position, _)| {
+            let (path, chunk) = chunks[position];
+            Excerpt {
+                path: dir.join(path).display().to_string(),
+                start_line: chunk.start_line,
+                end_line: chunk.end_line,
+                text: chunk.text.clone(),
+            }
+        })
+        .collect())
+}
+
+/// The message sent before the question, with the excerpts numbered for citing
+pub fn prompt(excerpts: &[Excerpt]) -> String {
+    let mut prompt = String::from(
+        "Answer the following question using these excerpts from my documents where they are relevant. \
+         Cite the excerpts you use by their number, like [1], and say so if they don't contain the answer.\n",
+    );
+    for (number, excerpt) in excerpts.iter().enumerate() {
+        prompt.push_str(&format!(
+            "\n[{}] {}\n````\n{}\n````\n",
+            number + 1,
+            excerpt.source(),
+            excerpt.text.trim_end()
+        ));
+    }
+    prompt
+}
+
+/// The index file of a directory, named after a hash of its path
+fn index_path(root: &Path) -> PathBuf {
+    let hash = fnv1a(root.to_string_lossy().as_bytes());
+    let name = root
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    Settings::config_dir()
+        .join(INDEX_DIR)
+        .join(format!("{name}-{hash:016x}.json"))
+}
+
+/// A missing, broken or outdated index is treated as empty
+fn load_index(path: &Path) -> Index {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str::<Index>(&content).ok())
+        .filter(|index| index.version == INDEX_VERSION)
+        .unwrap_or_else(|| Index {
+            version: INDEX_VERSION,
+            ..Index::default()
+        })
+}
+
+fn save_index(path: &Path, index: &Index) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, serde_json::to_string(index)?)?;
+    Ok(())
+}
+
+/// Re-chunks the files that are new or were modified since they were indexed
+/// and drops the ones that are gone. Returns the number of changed files.
+fn update(index: &mut Index, root: &Path) -> Result<usize> {
+    let files = collect_files(root)?;
+    let mut changed = 0;
+
+    let before = index.files.len();
+    index.files.retain(|path, _| files.contains_key(path));
+    changed += before - index.files.len();
+
+    let stale: Vec<(&String, &(PathBuf, u64, u64))> = files
+        .iter()
+        .filter(|(path, (_, mtime, size))| {
+            index
+                .files
+                .get(*path)
+                .is_none_or(|file| file.mtime != *mtime || file.size != *size)
+        })
+        .collect();
+    if stale.len() > 1 {
+        eprintln!("Indexing {} files in {}", stale.len(), root.display());
+    }
+
+    for (path, (full_path, mtime, size)) in stale {
+        changed += 1;
+        // Files that can't be read, or aren't text, are kept with no chunks
+        // so they aren't tried again until they change
+        let chunks = std::fs::read(full_path)
+            .ok()
+            .and_then(|content| String::from_utf8(content).ok())
+            .map(|content| chunk(&content, is_markdown(path)))
+            .unwrap_or_default();
+        index.files.insert(
+            path.clone(),
+            IndexedFile {
+                mtime: *mtime,
+                size: *size,
+                chunks,
+            },
+        );
+    }
+
+    Ok(changed)
+}
+
+/// The files to index by relative path, with their full path, mtime and size
+fn collect_files(root: &Path) -> Result<BTreeMap<String, (PathBuf, u64, u64)>> {
+    let mut files = BTreeMap::new();
+    let mut pending = vec![root.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let file_type = entry.file_type()?;
+            if name.starts_with('.') || file_type.is_symlink() {
+                continue;
+            }
+            if file_type.is_dir() {
+                if !SKIPPED_DIRS.contains(&name.as_str()) {
+                    pending.push(entry.path());
+                }
+                continue;
+            }
+
+            let path = entry.path();
+            let indexed = path
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| EXTENSIONS.contains(&extension.to_lowercase().as_str()));
+            let metadata = entry.metadata()?;
+            if !indexed || metadata.len() > MAX_FILE_SIZE {
+                continue;
+            }
+
+            let mtime = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or_default();
+            let relative = path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .replace('\\', "/");
+            files.insert(relative, (path, mtime, metadata.len()));
+        }
+    }
+
+    Ok(files)
+}
+
+fn is_markdown(path: &str) -> bool {
+    [".md", ".markdown", ".mdx"]
+        .iter()
+        .any(|extension| path.to_lowercase().ends_with(extension))
+}
+
+/// Splits a file into chunks of whole lines. Markdown is preferably cut
+/// before headings, so sections stay together.
+fn chunk(content: &str, markdown: bool) -> Vec<Chunk> {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+
+    while start < lines.len() {
+        let mut end = start;
+        let mut size = 0;
+        let mut at_heading = false;
+        while end < lines.len() {
+            let is_heading = markdown && lines[end].starts_with('#');
+            at_heading = is_heading && size >= CHUNK_SIZE / 3;
+            if end > start && (size >= CHUNK_SIZE || at_heading) {
+                break;
+            }
+            size += lines[end].len() + 1;
+            end += 1;
+        }
+
+        let text = lines[start..end].join("\n");
+        if !text.trim().is_empty() {
+            let terms = tokenize(&text);
+            chunks.push(Chunk {
+                start_line: start + 1,
+                end_line: end,
+                length: terms.values().sum(),
+                terms,
+                text,
+                embedding: None,
+            });
+        }
+
+        if end == lines.len() {
+            break;
+        }
+        // Overlap, but always move forward. A section is complete, the next
+        // chunk needs no part of it.
+        start = if at_heading {
+            end
+        } else {
+            end.saturating_sub(CHUNK_OVERLAP).max(start + 1)
+        };
+    }
+
+    chunks
+}
+
+/// Lowercase words and their counts. Chinese, Japanese and Korean text has no
+/// spaces, it is split into overlapping pairs of characters instead.
+fn tokenize(text: &str) -> HashMap<String, u32> {
+    let mut terms = HashMap::new();
+    let mut word = String::new();
+    let mut cjk: Vec<char> = Vec::new();
+
+    let mut flush = |word: &mut String, cjk: &mut Vec<char>| {
+        if !word.is_empty() {
+            *terms.entry(std::mem::take(word)).or_default() += 1;
+        }
+        match cjk.len() {
+            0 => {}
+            1 => *terms.entry(cjk[0].to_string()).or_default() += 1,
+            _ => {
+                for pair in cjk.windows(2) {
+                    *terms.entry(pair.iter().collect()).or_default() += 1;
+                }
+            }
+        }
+        cjk.clear();
+    };
+
+    for c in text.chars() {
+        if is_cjk(c) {
+            if !word.is_empty() {
+                flush(&mut word, &mut Vec::new());
+            }
+            cjk.push(c);
+        } else if c.is_alphanumeric() {
+            if !cjk.is_empty() {
+                flush(&mut String::new(), &mut cjk);
+            }
+            word.extend(c.to_lowercase());
+        } else {
+            flush(&mut word, &mut cjk);
+        }
+    }
+    flush(&mut word, &mut cjk);
+
+    terms
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30FF}' // Hiragana and Katakana
+        | '\u{3400}'..='\u{4DBF}' // CJK Extension A
+        | '\u{4E00}'..='\u{9FFF}' // CJK Unified Ideographs
+        | '\u{AC00}'..='\u{D7AF}' // Hangul Syllables
+        | '\u{F900}'..='\u{FAFF}' // CJK Compatibility Ideographs
+    )
+}
+
+/// Positions of the chunks, in the order of `Index::files`, with their BM25
+/// score, best first. Chunks without any of the terms are left out.
+fn rank_bm25(index: &Index, query_terms: &[String]) -> Vec<(usize, f64)> {
+    let chunks: Vec<&Chunk> = index.files.values().flat_map(|file| &file.chunks).collect();
+    if chunks.is_empty() {
+        return Vec::new();
+    }
+
+    let count = chunks.len() as f64;
+    let average_length = chunks
+        .iter()
+        .map(|chunk| f64::from(chunk.length))
+        .sum::<f64>()
+        / count;
+    let idf: Vec<f64> = query_terms
+        .iter()
+        .map(|term| {
+            let containing = chunks
+                .iter()
+                .filter(|chunk| chunk.terms.contains_key(term))
+                .count() as f64;
+            ((count - containing + 0.5) / (containing + 0.5) + 1.0).ln()
+        })
+        .collect();
+
+    let mut scores: Vec<(usize, f64)> = chunks
+        .iter()
+        .enumerate()
+        .map(|(position, chunk)| {
+            let length_norm =
+                1.0 - BM25_B + BM25_B * f64::from(chunk.length) / average_length.max(1.0);
+            let score = query_terms
+                .iter()
+                .zip(&idf)
+                .map(|(term, idf)| {
+                    let frequency = f64::from(chunk.terms.get(term).copied().unwrap_or_default());
+                    idf * frequency * (BM25_K1 + 1.0) / (frequency + BM25_K1 * length_norm)
+                })
+                .sum::<f64>();
+            (position, score)
+        })
+        .filter(|(_, score)| *score > 0.0)
+        .collect();
+    scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+    scores
+}
+
+/// Embeds the chunks that have no embedding yet, returns how many there were
+fn embed_chunks(index: &mut Index, embedder: &Embedder) -> Result<usize> {
+    let model = ai::embedding_model(embedder.provider)
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "{} does not offer embeddings, leave out --embeddings or use a provider that does, e.g. -p qwen",
+                embedder.provider
+            )
+        })?;
+    if index.embedding_model.as_deref() != Some(model) {
+        for chunk in index.files.values_mut().flat_map(|file| &mut file.chunks) {
+            chunk.embedding = None;
+        }
+        index.embedding_model = Some(model.to_owned());
+    }
+
+    let mut missing: Vec<&mut Chunk> = index
+        .files
+        .values_mut()
+        .flat_map(|file| &mut file.chunks)
+        .filter(|chunk| chunk.embedding.is_none())
+        .collect();
+    if missing.is_empty() {
+        return Ok(0);
+    }
+
+    eprintln!("Embedding {} chunks with {model}", missing.len());
+    let texts: Vec<String> = missing.iter().map(|chunk| chunk.text.clone()).collect();
+    let embeddings = ai::embeddings(embedder.provider, embedder.api_key, &texts, &embedder.http)
+        .with_context(|| "Failed to embed the documents")?;
+    for (chunk, embedding) in missing.iter_mut().zip(embeddings) {
+        chunk.embedding = Some(embedding);
+    }
+
+    Ok(missing.len())
+}
+
+/// Positions of the chunks by cosine similarity to the query, best first
+fn rank_embeddings(index: &Index, query: &[f32]) -> Vec<(usize, f64)> {
+    let mut scores: Vec<(usize, f64)> = index
+        .files
+        .values()
+        .flat_map(|file| &file.chunks)
+        .enumerate()
+        .filter_map(|(position, chunk)| {
+            let embedding = chunk.embedding.as_ref()?;
+            Some((position, cosine(query, embedding)))
+        })
+        .collect();
+    scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+    scores
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f64 {
+    let dot: f64 = a
+        .iter()
+        .zip(b)
+        .map(|(x, y)| f64::from(*x) * f64::from(*y))
+        .sum();
+    let norm = |v: &[f32]| v.iter().map(|x| f64::from(*x).powi(2)).sum::<f64>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 { 0.0 } else { dot / norms }
+}
+
+/// Combines two rankings by reciprocal rank fusion, which doesn't care that
+/// BM25 scores and cosine similarities are on different scales
+fn fuse(first: &[(usize, f64)], second: &[(usize, f64)]) -> Vec<(usize, f64)> {
+    let mut scores: HashMap<usize, f64> = HashMap::new();
+    for ranking in [first, second] {
+        for (rank, (position, _)) in ranking.iter().enumerate() {
+            *scores.entry(*position).or_default() += 1.0 / (RRF_K + rank as f64 + 1.0);
+        }
+    }
+    let mut scores: Vec<(usize, f64)> = scores.into_iter().collect();
+    scores.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    scores
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms(text: &str) -> Vec<(String, u32)> {
+        let mut terms: Vec<_> = tokenize(text).into_iter().collect();
+        terms.sort();
+        terms
+    }
+
+    fn term(term: &str, count: u32) -> (String, u32) {
+        (term.to_owned(), count)
+    }
+
+    #[test]
+    fn tokenizes_words_in_lowercase() {
+        assert_eq!(
+            terms("Hello, World! hello_world x2"),
+            [term("hello", 2), term("world", 2), term("x2", 1)]
+        );
+    }
+
+    #[test]
+    fn tokenizes_cjk_into_pairs() {
+        assert_eq!(
+            terms("数据库连接"),
+            [
+                term("库连", 1),
+                term("据库", 1),
+                term("数据", 1),
+                term("连接", 1)
+            ]
+        );
+        // A single character is kept, words around CJK text are split off
+        assert_eq!(
+            terms("用Rust写 テキスト"),
+            [
+                term("rust", 1),
+                term("キス", 1),
+                term("スト", 1),
+                term("テキ", 1),
+                term("写", 1),
+                term("用", 1)
+            ]
+        );
+    }
+
+    fn lines(chunks: &[Chunk]) -> Vec<(usize, usize)> {
+        chunks
+            .iter()
+            .map(|chunk| (chunk.start_line, chunk.end_line))
+            .collect()
+    }
+
+    #[test]
+    fn chunks_overlap_and_move_forward() {
+        let line = "word ".repeat(200);
+        let content = [line.as_str(); 3].join("\n");
+        assert_eq!(lines(&chunk(&content, false)), [(1, 2), (2, 3)]);
+
+        // Lines longer than a chunk still end up in one chunk each
+        let line = "x".repeat(CHUNK_SIZE * 2);
+        let content = [line.as_str(); 4].join("\n");
+        assert_eq!(
+            lines(&chunk(&content, false)),
+            [(1, 1), (2, 2), (3, 3), (4, 4)]
+        );
+    }
+
+    #[test]
+    fn chunks_markdown_at_headings() {
+        let section = format!("# Section\n{}", "text ".repeat(120));
+        let content = [section.as_str(); 3].join("\n");
+        let chunks = chunk(&content, true);
+        assert_eq!(lines(&chunks), [(1, 2), (3, 4), (5, 6)]);
+        assert!(chunks[1].text.starts_with("# Section"));
+        // Without markdown the sections are not kept apart
+        assert_eq!(lines(&chunk(&content, false)), [(1, 6)]);
+    }
+
+    #[test]
+    fn skips_blank_chunks() {
+        assert!(chunk("", false).is_empty());
+        assert!(chunk("\n  \n\t\n", false).is_empty());
+        let chunks = chunk("one\ntwo", false);
+        assert_eq!(lines(&chunks), [(1, 2)]);
+        assert_eq!(chunks[0].length, 2);
+    }
+}
@@ -0,0 +1,418 @@
+use serde_json::Value;
+use std::fmt;
+
+use crate::command::AIProvider;
+
+/// Exit code for errors that aren't an [`AskError`] with a code of its own
+pub static EXIT_FAILURE: i32 = 1;
+
+/// Longest part of an error response shown when it has no message field
+static MAX_BODY_CHARS: usize = 300;
+
+/// The ways asking a question can fail
+#[derive(Debug)]
+pub enum AskError {
+    /// The settings are incomplete or invalid, e.g. no API key is set
+    Config { message: String, hint: String },
+    /// The provider rejected the API key
+    Auth {
+        provider: AIProvider,
+        message: String,
+    },
+    /// Too many requests in a short time
+    RateLimit {
+        provider: AIProvider,
+        message: String,
+    },
+    /// The account has no balance or quota left
+    Quota {
+        provider: AIProvider,
+        message: String,
+    },
+    /// The provider doesn't offer the model, or not to this account
+    BadModel {
+        provider: AIProvider,
+        model: String,
+        message: String,
+    },
+    /// The moderation of the provider blocked the question or the answer
+    ContentFilter {
+        provider: AIProvider,
+        message: String,
+    },
+    /// Any other error status from the provider
+    Api {
+        provider: AIProvider,
+        status: u16,
+        message: String,
+    },
+    /// The provider could not be reached, or the connection broke
+    Network(String),
+    /// A connect, first-token, idle or request timeout was hit, the message
+    /// names which
+    Timeout(String),
+    /// The request was cancelled before the answer was complete
+    Cancelled,
+    /// Any other failure
+    Other(anyhow::Error),
+}
+
+impl AskError {
+    pub fn config(message: impl Into<String>, hint: impl Into<String>) -> Self {
+        AskError::Config {
+            message: message.into(),
+            hint: hint.into(),
+        }
+    }
+
+    pub fn missing_key(provider: AIProvider) -> Self {
+        AskError::config(
+            format!("{} API key is not set", provider.name()),
+            format!(
+                "Set it with `export {}={{your key}}`",
+                key_variable(provider)
+            ),
+        )
+    }
+
+    /// The error of a response with an error status. The message is taken
+    /// from the error object the providers send, which also tells the
+    /// failures apart that share a status.
+    pub fn from_response(provider: AIProvider, model: &str, status: u16, body: &str) -> Self {
+        let json: Value = serde_json::from_str(body).unwrap_or_default();
+        // OpenAI style {"error": {"message", "type", "code"}}, or the fields
+        // at the top level
+        let error = if json["error"].is_object() {
+            &json["error"]
+        } else {
+            &json
+        };
+        let message = error["message"]
+            .as_str()
+            .or_else(|| json["error"].as_str())
+            .map(str::to_owned)
+            .unwrap_or_else(|| {
+                let body = body.trim();
+                if body.is_empty() {
+                    reqwest::StatusCode::from_u16(status)
+                        .ok()
+                        .and_then(|status| status.canonical_reason())
+                        .unwrap_or("No details")
+                        .to_owned()
+                } else {
+                    body.chars().take(MAX_BODY_CHARS).collect()
+                }
+            });
+        let code = [&error["code"], &error["type"]]
+            .iter()
+            .filter_map(|field| match field {
+                Value::String(code) => Some(code.to_lowercase()),
+                Value::Number(code) => Some(code.to_string()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        let lower = message.to_lowercase();
+        let mentions = |words: &[&str]| {
+            words
+                .iter()
+                .any(|word| code.contains(word) || lower.contains(word))
+        };
+
+        if mentions(&[
+            "content_filter",
+            "content_policy",
+            "data_inspection_failed",
+            "datainspectionfailed",
+            "inappropriate content",
+        ]) {
+            AskError::ContentFilter { provider, message }
+        } else if status == 402
+            || mentions(&[
+                "insufficient_quota",
+                "exceeded your current quota",
+                "insufficient_balance",
+                "insufficient balance",
+                "arrearage",
+            ])
+        {
+            AskError::Quota { provider, message }
+        } else if status == 401
+            || status == 403
+            || mentions(&["invalid_api_key", "invalid api key", "incorrect api key"])
+        {
+            AskError::Auth { provider, message }
+        } else if status == 429 {
+            AskError::RateLimit { provider, message }
+        } else if !model.is_empty()
+            && (status == 404
+                || mentions(&["model_not_found", "model not exist", "model_not_exist"])
+                || (lower.contains("model")
+                    && (lower.contains("does not exist") || lower.contains("not found"))))
+        {
+            AskError::BadModel {
+                provider,
+                model: model.to_owned(),
+                message,
+            }
+        } else {
+            AskError::Api {
+                provider,
+                status,
+                message,
+            }
+        }
+    }
+
+    /// How to fix the error, if the user can do anything about it
+    pub fn hint(&self) -> Option<String> {
+        let hint = match self {
+            AskError::Config { hint, .. } => hint.clone(),
+            AskError::Auth { provider, .. } => format!(
+                "Check the key in {}, it may be mistyped, expired or revoked",
+                key_variable(*provider)
+            ),
+            AskError::RateLimit { .. } => {
+                "Wait a moment and try again, or compare fewer models at once".to_owned()
+            }
+            AskError::Quota { provider, .. } => format!(
+                "Top up the {} account or raise its quota, then try again",
+                provider.name()
+            ),
+            AskError::BadModel { provider, .. } => format!(
+                "See the models it offers with `ask models --provider {provider} --refresh`, and choose one with `ask config model` or --model"
+            ),
+            AskError::ContentFilter { .. } => {
+                "Rephrase the question, the provider's moderation rejected it".to_owned()
+            }
+            AskError::Api { status, .. } if *status >= 500 => {
+                "The provider has a problem, try again later".to_owned()
+            }
+            AskError::Network(_) => {
+                "Check the connection, or the proxy and certificates with `ask config network`"
+                    .to_owned()
+            }
+            AskError::Timeout(_) => {
+                "Raise the limit with `ask config timeout`, e.g. `ask config timeout --first-token 120`"
+                    .to_owned()
+            }
+            AskError::Api { .. } | AskError::Cancelled | AskError::Other(_) => return None,
+        };
+        Some(hint)
+    }
+
+    /// The exit code of the command line tool, see the README for the list
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            AskError::Config { .. } => 3,
+            AskError::Auth { .. } => 4,
+            AskError::RateLimit { .. } => 5,
+            AskError::Quota { .. } => 6,
+            AskError::BadModel { .. } => 7,
+            AskError::ContentFilter { .. } => 8,
+            AskError::Api { .. } => 9,
+            AskError::Network(_) => 10,
+            AskError::Timeout(_) => 11,
+            // Like a shell reports an interrupted command
+            AskError::Cancelled => 130,
+            AskError::Other(_) => EXIT_FAILURE,
+        }
+    }
+}
+
+/// The environment variable holding the API key of a provider
+fn key_variable(provider: AIProvider) -> String {
+    format!("ASK_{}_KEY", provider.to_string().to_uppercase())
+}
+
+impl fmt::Display for AskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AskError::Config { message, .. }
+            | AskError::Network(message)
+            | AskError::Timeout(message) => write!(f, "{message}"),
+            AskError::Auth { provider, message } => {
+                write!(f, "{} rejected the API key: {message}", provider.name())
+            }
+            AskError::RateLimit { provider, message } => {
+                write!(f, "{} is limiting the requests: {message}", provider.name())
+            }
+            AskError::Quota { provider, message } => {
+                write!(
+                    f,
+                    "The {} account has no balance or quota left: {message}",
+                    provider.name()
+                )
+            }
+            AskError::BadModel {
+                provider,
+                model,
+                message,
+            } => write!(
+                f,
+                "{} can't answer with the model {model}: {message}",
+                provider.name()
+            ),
+            AskError::ContentFilter { provider, message } => {
+                write!(f, "{} blocked the content: {message}", provider.name())
+            }
+            AskError::Api {
+                provider,
+                status,
+                message,
+            } => {
+                let status = reqwest::StatusCode::from_u16(*status)
+                    .map(|status| status.to_string())
+                    .unwrap_or_else(|_| status.to_string());
+                write!(
+                    f,
+                    "{} failed with status {status}: {message}",
+                    provider.name()
+                )
+            }
+            AskError::Cancelled => write!(f, "The request was cancelled"),
+            AskError::Other(err) => write!(f, "{err:#}"),
+        }
+    }
+}
+
+// The messages already include their causes, so there is no source
+impl std::error::Error for AskError {}
+
+impl From<anyhow::Error> for AskError {
+    fn from(err: anyhow::Error) -> Self {
+        match err.downcast::<AskError>() {
+            Ok(err) => err,
+            Err(err) => AskError::Other(err),
+        }
+    }
+}
+
+impl From<reqwest::Error> for AskError {
+    fn from(err: reqwest::Error) -> Self {
+        AskError::Network(format!("{:#}", anyhow::Error::from(err)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classify(provider: AIProvider, status: u16, body: &str) -> AskError {
+        AskError::from_response(provider, "some-model", status, body)
+    }
+
+    #[test]
+    fn deepseek_errors() {
+        let err = classify(
+            AIProvider::DeepSeek,
+            402,
+            r#"{"error":{"message":"Insufficient Balance","type":"unknown_error","param":null,"code":"invalid_request_error"}}"#,
+        );
+        assert!(
+            matches!(&err, AskError::Quota { message, .. } if message == "Insufficient Balance")
+        );
+        assert_eq!(err.exit_code(), 6);
+
+        let err = classify(
+            AIProvider::DeepSeek,
+            401,
+            r#"{"error":{"message":"Authentication Fails, Your api key: ****abcd is invalid","type":"authentication_error","param":null,"code":"invalid_request_error"}}"#,
+        );
+        assert!(matches!(err, AskError::Auth { .. }));
+        assert_eq!(
+            err.hint().unwrap(),
+            "Check the key in ASK_DEEPSEEK_KEY, it may be mistyped, expired or revoked"
+        );
+
+        let err = classify(
+            AIProvider::DeepSeek,
+            429,
+            r#"{"error":{"message":"Rate limit reached","type":"rate_limit_error","param":null,"code":"rate_limit_exceeded"}}"#,
+        );
+        assert!(matches!(err, AskError::RateLimit { .. }));
+    }
+
+    #[test]
+    fn qwen_errors() {
+        let err = classify(
+            AIProvider::Qwen,
+            401,
+            r#"{"error":{"message":"Incorrect API key provided. ","type":"invalid_request_error","param":null,"code":"invalid_api_key"},"request_id":"1"}"#,
+        );
+        assert!(matches!(err, AskError::Auth { .. }));
+
+        let err = classify(
+            AIProvider::Qwen,
+            404,
+            r#"{"error":{"message":"The model `some-model` does not exist or you do not have access to it.","type":"invalid_request_error","param":null,"code":"model_not_found"},"request_id":"1"}"#,
+        );
+        assert!(matches!(&err, AskError::BadModel { model, .. } if model == "some-model"));
+
+        // Qwen reports moderation and an unpaid account as 400
+        let err = classify(
+            AIProvider::Qwen,
+            400,
+            r#"{"error":{"code":"data_inspection_failed","param":null,"message":"Input data may contain inappropriate content.","type":"data_inspection_failed"},"request_id":"1"}"#,
+        );
+        assert!(matches!(err, AskError::ContentFilter { .. }));
+        let err = classify(
+            AIProvider::Qwen,
+            400,
+            r#"{"error":{"code":"Arrearage","param":null,"message":"Access denied, please make sure your account is in good standing.","type":"Arrearage"},"request_id":"1"}"#,
+        );
+        assert!(matches!(err, AskError::Quota { .. }));
+
+        let err = classify(
+            AIProvider::Qwen,
+            429,
+            r#"{"error":{"message":"Requests rate limit exceeded, please try again later.","type":"limit_requests","param":null,"code":"limit_requests"},"request_id":"1"}"#,
+        );
+        assert!(matches!(err, AskError::RateLimit { .. }));
+    }
+
+    #[test]
+    fn grok_errors() {
+        // Grok sends the message as the error field itself
+        let err = classify(
+            AIProvider::Grok,
+            400,
+            r#"{"code":"Client specified an invalid argument","error":"Incorrect API key provided: xa***yz. You can obtain an API key from https://console.x.ai."}"#,
+        );
+        assert!(
+            matches!(&err, AskError::Auth { message, .. } if message.starts_with("Incorrect API key"))
+        );
+
+        let err = classify(
+            AIProvider::Grok,
+            404,
+            r#"{"code":"Some requested entity was not found","error":"The model some-model does not exist or your team does not have access to it."}"#,
+        );
+        assert!(matches!(err, AskError::BadModel { .. }));
+        assert_eq!(err.exit_code(), 7);
+
+        let err = classify(
+            AIProvider::Grok,
+            429,
+            r#"{"code":"Too many requests","error":"Your team has exceeded its requests per second limit."}"#,
+        );
+        assert!(matches!(err, AskError::RateLimit { .. }));
+    }
+
+    #[test]
+    fn bodies_without_an_error_object() {
+        let err = classify(AIProvider::Grok, 503, "");
+        assert!(matches!(
+            &err,
+            AskError::Api { status: 503, message, .. } if message == "Service Unavailable"
+        ));
+        assert!(err.hint().is_some());
+
+        let err = classify(AIProvider::DeepSeek, 502, &"<html>".repeat(100));
+        assert!(matches!(&err, AskError::Api { message, .. } if message.len() == MAX_BODY_CHARS));
+
+        // Without a model, a 404 is not about the model
+        let err = AskError::from_response(AIProvider::Qwen, "", 404, "");
+        assert!(matches!(err, AskError::Api { status: 404, .. }));
+    }
+}
@@ -1,9 +1,16 @@
-mod ai;
-mod command;
-mod settings;
+pub mod ai;
+mod client;
+pub mod command;
+mod error;
+pub mod models;
+pub mod settings;
+pub mod util;
 
 pub use ai::*;
-pub use command::*;
+pub use client::*;
+pub use command::{AIProvider, GenerationParams, ModelChoice};
+pub use error::*;
+pub use models::*;
 pub use settings::*;
 
 #[macro_export]
@@ -1,66 +1,129 @@
-mod ai;
-mod command;
-mod settings;
-
-use crate::command::{AIProvider, AskArgsParser, ConfigCommand};
-use crate::settings::Settings;
+mod cache;
+mod compare;
+mod docs;
+mod markdown;
+mod mcp;
+mod plain;
+mod serve;
+mod tools;
+mod tui;
+mod usage;
+
+use crate::ai::Message;
+use crate::command::{AIProvider, AskArgsParser, ConfigCommand, QuestionArgs, QuestionParser};
+use crate::settings::{ModelPrice, Preset, Settings};
 use anyhow::{Context, Ok, Result};
-use ask::dprintln;
+use ask::{Ask, AskError, ai, command, dprintln, models, settings, util};
+use clap::ValueEnum;
+use clap::error::{ContextKind, ErrorKind};
 use clap::{CommandFactory, Parser};
-use command::AIModel;
-use ratatui::crossterm::event;
-use ratatui::crossterm::event::{Event, KeyCode};
-use ratatui::layout::{Constraint, Layout};
-use ratatui::style::Stylize;
-use ratatui::widgets::{Paragraph, Wrap};
+use command::{GenerationParams, OutputMode};
 use std::collections::HashMap;
-use std::io::{Write, stdout};
+use std::ffi::OsString;
+use std::io::{IsTerminal, Write, stdout};
 use std::process::exit;
-use std::sync::mpsc::Receiver;
-use std::time::Duration;
 
-fn main() -> Result<()> {
+fn main() {
+    if let Err(err) = run() {
+        // The typed error may be wrapped in context
+        let typed = err
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<AskError>());
+        // The user interrupted it, there is nothing to report
+        if !matches!(typed, Some(AskError::Cancelled)) {
+            eprintln!("Error: {err:#}");
+        }
+        if let Some(hint) = typed.and_then(AskError::hint) {
+            eprintln!("Hint: {hint}");
+        }
+        exit(typed.map_or(ask::EXIT_FAILURE, AskError::exit_code));
+    }
+}
+
+fn run() -> Result<()> {
     // Load settings
-    let mut settings = Settings::load().with_context(|| "Failed to load settings")?;
+    let mut settings = Settings::load().map_err(|err| {
+        AskError::config(
+            format!("Failed to load settings: {err:#}"),
+            "Fix or remove ~/.config/ask.json, or check the ASK_* environment variables",
+        )
+    })?;
 
     dprintln!("{:#?}", settings);
 
     // Parse command line arguments
 
-    let std_args: Vec<String> = std::env::args().skip(1).collect();
+    let args = parse_args();
 
-    if std_args.is_empty()
-        || std_args[0] == "help"
-        || std_args[0] == "--help"
-        || std_args[0] == "-h"
-    {
-        AskArgsParser::command().print_long_help()?;
-        exit(0);
+    dprintln!("{:#?}", args);
+
+    match args.command {
+        // Only these change the settings. Saving after other commands would
+        // write the values from ASK_* environment variables to the file.
+        Some(command::AskCommand::Config(cmd)) => {
+            handle_config_command(cmd, &mut settings)?;
+            settings.save().with_context(|| "Failed to save settings")?;
+        }
+        Some(command::AskCommand::Preset(cmd)) => {
+            handle_preset_command(cmd, &mut settings)?;
+            settings.save().with_context(|| "Failed to save settings")?;
+        }
+        Some(command::AskCommand::Usage(args)) => usage::print_summary(&settings, args.days)?,
+        Some(command::AskCommand::Models(args)) => handle_models_command(args, &settings)?,
+        Some(command::AskCommand::Cache(cmd)) => handle_cache_command(cmd)?,
+        Some(command::AskCommand::Serve(args)) => serve::run(&args.host, args.port, &settings)?,
+        Some(command::AskCommand::Completions(args)) => {
+            let mut cmd = AskArgsParser::command();
+            clap_complete::generate(args.shell, &mut cmd, "ask", &mut stdout());
+        }
+        None if args.question.question.is_empty() && args.question.images.is_empty() => {
+            AskArgsParser::command().print_long_help()?;
+            exit(0);
+        }
+        None => {
+            let settings = override_settings(&args.question, &settings);
+            handle_question(&args.question, &settings)?;
+        }
     }
 
-    if std_args[0] != "config" && std_args[0] != "preset" {
-        let (preset, question) = match std_args.len() {
-            0 => unreachable!("We have already checked for empty args"),
-            1 => (String::new(), std_args[0].clone()),
-            _ => (std_args[0].clone(), std_args[1..].join(" ")),
-        };
-        handle_question(preset, question, &settings)?;
-    } else {
-        drop(std_args);
+    Ok(())
+}
 
-        let args = AskArgsParser::parse();
+/// Parses the command line, falling back to a question when a subcommand
+/// doesn't parse, as in `ask config is what in nginx?`
+fn parse_args() -> AskArgsParser {
+    let raw: Vec<OsString> = std::env::args_os().collect();
+    let mut args = match AskArgsParser::try_parse_from(&raw) {
+        std::result::Result::Ok(args) => args,
+        Err(err) => parse_question(err, &raw),
+    };
 
-        dprintln!("{:#?}", args);
+    // The question takes the rest of the arguments, `ask -- word ...` takes
+    // it literally
+    let question = &args.question.question;
+    args.question.literal =
+        raw.len() > question.len() && raw[raw.len() - question.len() - 1] == "--";
+    args
+}
 
-        match args.command {
-            command::AskCommand::Config(cmd) => handle_config_command(cmd, &mut settings)?,
-            command::AskCommand::Preset(cmd) => handle_preset_command(cmd, &mut settings)?,
-        }
+/// The command line as a question, or the error if it isn't one either
+fn parse_question(err: clap::Error, raw: &[OsString]) -> AskArgsParser {
+    // A suggestion means a mistyped subcommand or flag, which is not a question
+    let suggested = err.get(ContextKind::SuggestedSubcommand).is_some()
+        || err.get(ContextKind::SuggestedArg).is_some();
+    if matches!(
+        err.kind(),
+        ErrorKind::InvalidSubcommand | ErrorKind::UnknownArgument
+    ) && !suggested
+        && let std::result::Result::Ok(parsed) = QuestionParser::try_parse_from(raw)
+    {
+        return AskArgsParser {
+            command: None,
+            question: parsed.question,
+        };
     }
 
-    settings.save().with_context(|| "Failed to save settings")?;
-
-    Ok(())
+    err.exit()
 }
 
 fn handle_preset_command(cmd: command::PresetCommand, settings: &mut Settings) -> Result<()> {
@@ -70,12 +133,14 @@ fn handle_preset_command(cmd: command::PresetCommand, settings: &mut Settings) -
                 settings.presets = Some(HashMap::new());
             }
             if let Some(presets) = &mut settings.presets {
-                presets.insert(args.name.clone(), args.prompt.join(" "));
+                let preset = Preset::new(args.prompt.join(" "), args.params);
                 println!(
-                    "Preset '{}' set with prompt: {}",
+                    "Preset '{}' set with prompt: {}{}",
                     args.name,
-                    args.prompt.join(" ")
+                    preset.prompt(),
+                    describe_params(&preset.params())
                 );
+                presets.insert(args.name, preset);
             }
         }
         command::PresetSubcommand::List => {
@@ -87,16 +152,21 @@ fn handle_preset_command(cmd: command::PresetCommand, settings: &mut Settings) -
             if presets.is_empty() {
                 println!("No presets found");
             } else {
-                for (name, prompt) in presets {
-                    println!("{} => {}", name, prompt);
+                for (name, preset) in presets {
+                    println!(
+                        "{} => {}{}",
+                        name,
+                        preset.prompt(),
+                        describe_params(&preset.params())
+                    );
                 }
             }
         }
         command::PresetSubcommand::Remove(args) => {
             match &mut settings.presets {
                 Some(presets) => match presets.remove(&args.name) {
-                    Some(prompt) => {
-                        println!("Removed preset '{}': {}", args.name, prompt);
+                    Some(preset) => {
+                        println!("Removed preset '{}': {}", args.name, preset.prompt());
                     }
                     None => {
                         println!("No preset found for '{}'", args.name);
@@ -121,198 +191,615 @@ fn handle_config_command(cmd: ConfigCommand, settings: &mut Settings) -> Result<
                 .map(|p| p.to_string())
                 .unwrap_or("".to_string());
 
-            let model = settings
-                .model
-                .as_ref()
-                .map(|m| m.to_string())
-                .unwrap_or("".to_string());
+            let model = settings.model.clone().unwrap_or_default();
 
             let timeout = settings
                 .timeout
                 .map(|t| t.to_string())
                 .unwrap_or("".to_string());
 
+            let output = settings.output.unwrap_or_default();
+            let keep_answer = settings.keep_answer.unwrap_or(false);
+
             println!("provider => {provider}");
             println!("model => {model}");
             println!("timeout => {timeout}");
+            if let Some(first_token) = settings.first_token_timeout {
+                println!("first-token-timeout => {first_token}");
+            }
+            if let Some(idle) = settings.idle_timeout {
+                println!("idle-timeout => {idle}");
+            }
+            print_network(settings);
+            println!("output => {output}");
+            println!("keep-answer => {keep_answer}");
+
+            if let Some(params) = &settings.params {
+                print_params(params);
+            }
+
+            if let Some(commands) = &settings.tool_commands {
+                println!("tool-commands => {commands:?}");
+            }
+
+            if let Some(models) = &settings.fallback {
+                println!("fallback => {models:?}");
+            }
+
+            if let Some(servers) = &settings.mcp_servers {
+                for (name, server) in servers {
+                    let command: Vec<&str> = std::iter::once(&server.command)
+                        .chain(&server.args)
+                        .map(String::as_str)
+                        .collect();
+                    println!("mcp.{name} => {}", command.join(" "));
+                }
+            }
+
+            if let Some(cache) = settings.cache {
+                println!("cache => {cache}");
+            }
+            if let Some(ttl) = settings.cache_ttl {
+                println!("cache-ttl => {ttl}");
+            }
+            if let Some(max_size) = settings.cache_max_size {
+                println!("cache-max-size => {max_size}");
+            }
+
+            if let Some(prices) = &settings.prices {
+                for (model, price) in prices {
+                    println!("price.{model} => {} / {}", price.input, price.output);
+                }
+            }
         }
         command::ConfigSubcommand::Provider(args) => {
             settings.provider = Some(args.provider);
             println!("AI provider set to: {}", args.provider);
         }
         command::ConfigSubcommand::Timeout(args) => {
-            settings.timeout = Some(args.timeout);
-            println!("Request timeout set to: {} seconds", args.timeout);
+            if let Some(timeout) = args.timeout {
+                settings.timeout = Some(timeout);
+                println!("Request timeout set to: {timeout} seconds");
+            }
+            if let Some(first_token) = args.first_token {
+                settings.first_token_timeout = Some(first_token);
+                println!("First-token timeout set to: {first_token} seconds");
+            }
+            if let Some(idle) = args.idle {
+                settings.idle_timeout = Some(idle);
+                println!("Idle timeout set to: {idle} seconds");
+            }
         }
         command::ConfigSubcommand::Model(args) => {
-            settings.model = Some(args.model);
+            warn_unknown_model(&args.model, settings);
             println!("AI model set to: {}", args.model);
+            settings.model = Some(args.model);
+        }
+        command::ConfigSubcommand::Output(args) => {
+            settings.output = Some(args.output);
+            println!("Output mode set to: {}", args.output);
+        }
+        command::ConfigSubcommand::KeepAnswer(args) => {
+            settings.keep_answer = Some(args.keep);
+            println!("Keep answer after exit set to: {}", args.keep);
+        }
+        command::ConfigSubcommand::Price(args) => {
+            let price = ModelPrice {
+                input: args.input,
+                output: args.output,
+            };
+            settings
+                .prices
+                .get_or_insert_with(HashMap::new)
+                .insert(args.model.clone(), price);
+            println!(
+                "Price of {} set to: {} per million prompt tokens, {} per million completion tokens",
+                args.model, args.input, args.output
+            );
+        }
+        command::ConfigSubcommand::Params(args) => {
+            let params = if args.reset {
+                args.params
+            } else {
+                args.params.or(&settings.params.take().unwrap_or_default())
+            };
+            if params.is_empty() {
+                settings.params = None;
+                println!("Default generation parameters cleared");
+            } else {
+                println!("Default generation parameters set to:");
+                print_params(&params);
+                settings.params = Some(params);
+            }
+        }
+        command::ConfigSubcommand::ToolCommands(args) => {
+            if args.reset {
+                settings.tool_commands = None;
+                println!(
+                    "Tool commands reset to: {:?}",
+                    tools::allowed_commands(settings)
+                );
+            } else {
+                println!("Tool commands set to: {:?}", args.commands);
+                settings.tool_commands = Some(args.commands);
+            }
+        }
+        command::ConfigSubcommand::Fallback(args) => {
+            if args.reset {
+                settings.fallback = None;
+                println!("Fallback models removed");
+            } else {
+                let models: Vec<String> = args.models.iter().map(|m| m.to_string()).collect();
+                println!("Fallback models set to: {models:?}");
+                settings.fallback = Some(models);
+            }
+        }
+        command::ConfigSubcommand::Network(args) => {
+            if args.reset {
+                settings.proxy = None;
+                settings.no_proxy = None;
+                settings.ca_bundle = None;
+                settings.insecure_skip_verify = None;
+                settings.connect_timeout = None;
+            }
+            settings.proxy = args.proxy.or(settings.proxy.take());
+            settings.no_proxy = args.no_proxy.or(settings.no_proxy.take());
+            settings.ca_bundle = args.ca_bundle.or(settings.ca_bundle.take());
+            settings.insecure_skip_verify = args
+                .insecure_skip_verify
+                .or(settings.insecure_skip_verify.take());
+            settings.connect_timeout = args.connect_timeout.or(settings.connect_timeout.take());
+            println!("Network settings set to:");
+            print_network(settings);
+        }
+        command::ConfigSubcommand::Cache(args) => {
+            settings.cache = Some(args.enabled);
+            println!("Answer cache set to: {}", args.enabled);
+            if let Some(ttl) = args.ttl {
+                settings.cache_ttl = Some(ttl);
+                println!("Cache TTL set to: {ttl} hours");
+            }
+            if let Some(max_size) = args.max_size {
+                settings.cache_max_size = Some(max_size);
+                println!("Cache size limit set to: {max_size} MB");
+            }
         }
     }
 
     Ok(())
 }
 
-fn handle_question(preset: String, question: String, settings: &Settings) -> Result<()> {
-    validate_ai_settings(settings)?;
+fn handle_cache_command(cmd: command::CacheCommand) -> Result<()> {
+    match cmd.command {
+        command::CacheSubcommand::Stats => {
+            let stats = cache::stats().with_context(|| "Failed to read the cache")?;
+            println!("Answers: {}", stats.entries);
+            println!("Size: {:.1} MB", stats.bytes as f64 / (1024.0 * 1024.0));
+            println!("Hits: {}", stats.hits);
+            println!("Tokens saved: {}", stats.saved_tokens);
+        }
+        command::CacheSubcommand::Clear => {
+            let removed = cache::clear().with_context(|| "Failed to clear the cache")?;
+            println!("Removed {removed} cached answers");
+        }
+    }
 
-    let mut messages = Vec::<String>::new();
+    Ok(())
+}
 
-    let preset_prompt = settings
-        .presets
-        .as_ref()
-        .and_then(|presets| presets.get(&preset))
-        .map(|prompt| prompt.to_owned())
-        .unwrap_or_default();
+/// Warns if the model is not offered by the configured provider. The model
+/// is set anyway, the list may be out of date or not include every model.
+fn warn_unknown_model(model: &str, settings: &Settings) {
+    let Some(provider) = settings.provider else {
+        return;
+    };
+    let models = match settings.api_key(provider) {
+        Some(key) => models::list(provider, key, &settings.http_options_for(provider), false),
+        None => Ok(models::cached(provider)),
+    };
 
-    if preset_prompt.is_empty() {
-        messages.push(format!("{preset} {question}"));
-    } else {
-        messages.push(preset_prompt);
-        messages.push(question.to_owned());
+    let models = match models {
+        Err(err) => {
+            eprintln!("Warning: could not fetch the models of {provider}: {err}");
+            return;
+        }
+        models => models.unwrap_or_default(),
+    };
+    if !models.iter().any(|known| known == model) {
+        eprintln!(
+            "Warning: {provider} does not list the model {model}, see `ask models --provider {provider}`"
+        );
     }
+}
 
-    let messages = messages
-        .iter()
-        .map(|msg| msg.trim().to_string())
-        .filter(|msg| !msg.is_empty())
-        .collect::<Vec<String>>();
-
-    dprintln!("messages: {:?}", messages);
+fn handle_models_command(args: command::ModelsArgs, settings: &Settings) -> Result<()> {
+    let providers = match args.provider {
+        Some(provider) => vec![provider],
+        None => AIProvider::value_variants().to_vec(),
+    };
 
-    stdout().flush().unwrap();
+    for provider in providers {
+        let Some(key) = settings.api_key(provider) else {
+            // Asking for one provider without a key is an error, listing all just skips it
+            if args.provider.is_some() {
+                return Err(AskError::missing_key(provider).into());
+            }
+            println!("{provider} (API key is not set)\n");
+            continue;
+        };
 
-    let reply = match settings.provider.unwrap() {
-        AIProvider::DeepSeek => {
-            let key = settings.deepseek_key.as_ref().unwrap();
-            let model = settings.model.as_ref().unwrap().name();
-            ai::deepseek(&messages, key, model, settings.timeout)?
+        let models = models::list(
+            provider,
+            key,
+            &settings.http_options_for(provider),
+            args.refresh,
+        )
+        .with_context(|| format!("Failed to fetch the models of {provider}"))?;
+
+        println!("{provider}");
+        for model in models {
+            let current =
+                settings.provider == Some(provider) && settings.model.as_deref() == Some(&model);
+            println!("  {model}{}", if current { " *" } else { "" });
         }
-        AIProvider::Grok => {
-            todo!()
-        }
-        AIProvider::Qwen => {
-            let key = settings.qwen_key.as_ref().unwrap();
-            let model = settings.model.as_ref().unwrap().name();
-            ai::qwen(&messages, key, model, settings.timeout)?
-        }
-    };
+        println!();
+    }
 
-    handle_reply(&question.as_ref(), reply)?;
     Ok(())
 }
 
-fn handle_reply(question: &str, rx: Receiver<String>) -> Result<()> {
-    let mut terminal = ratatui::init();
+fn print_network(settings: &Settings) {
+    if let Some(connect_timeout) = settings.connect_timeout {
+        println!("connect-timeout => {connect_timeout}");
+    }
+    if let Some(proxy) = &settings.proxy {
+        println!("proxy => {proxy}");
+    }
+    if let Some(no_proxy) = &settings.no_proxy {
+        println!("no-proxy => {no_proxy}");
+    }
+    if let Some(ca_bundle) = &settings.ca_bundle {
+        println!("ca-bundle => {}", ca_bundle.display());
+    }
+    if let Some(insecure_skip_verify) = settings.insecure_skip_verify {
+        println!("insecure-skip-verify => {insecure_skip_verify}");
+    }
+}
 
-    let mut markdown_content = String::from("Loading...");
-    let mut scroll = 0;
+fn print_params(params: &GenerationParams) {
+    if let Some(temperature) = params.temperature {
+        println!("temperature => {temperature}");
+    }
+    if let Some(top_p) = params.top_p {
+        println!("top-p => {top_p}");
+    }
+    if let Some(max_tokens) = params.max_tokens {
+        println!("max-tokens => {max_tokens}");
+    }
+    if let Some(seed) = params.seed {
+        println!("seed => {seed}");
+    }
+    if !params.stop.is_empty() {
+        println!("stop => {:?}", params.stop);
+    }
+}
 
-    loop {
-        rx.recv()
-            .map(|content| {
-                markdown_content = content;
-            })
-            .unwrap_or_else(|_| {
-                return;
-            });
-
-        terminal.draw(|f| {
-            let area = f.area();
-            let chunks = Layout::vertical([Constraint::Length(1), Constraint::Fill(1)].as_ref())
-                .spacing(1)
-                .margin(1)
-                .split(area);
-
-            let title_paragraph = Paragraph::new("[Press q to exit]")
-                .bold()
-                .alignment(ratatui::layout::Alignment::Left);
-
-            let md = format!(
-                "# [Question]\n\n{}\n\n---\n\n# [Response]\n\n{}",
-                question, markdown_content
-            );
-            let md = tui_markdown::from_str(&md);
-            let paragraph = Paragraph::new(md)
-                .alignment(ratatui::layout::Alignment::Left)
-                .wrap(Wrap { trim: true })
-                .scroll((scroll, 0));
-
-            f.render_widget(title_paragraph, chunks[0]);
-            f.render_widget(paragraph, chunks[1]);
-        })?;
-
-        if event::poll(Duration::from_millis(50))? {
-            if let Event::Key(key) = event::read()? {
-                match key.code {
-                    KeyCode::Char('q') => break,
-                    KeyCode::Up => {
-                        if scroll > 0 {
-                            scroll -= 1;
-                        }
-                    }
-                    KeyCode::Down => {
-                        scroll += 1;
-                    }
-                    _ => {}
-                }
-            }
-        }
+/// Short summary of the parameters set, e.g. " (temperature 0.2, max-tokens 100)"
+fn describe_params(params: &GenerationParams) -> String {
+    let mut parts = Vec::new();
+    if let Some(temperature) = params.temperature {
+        parts.push(format!("temperature {temperature}"));
+    }
+    if let Some(top_p) = params.top_p {
+        parts.push(format!("top-p {top_p}"));
+    }
+    if let Some(max_tokens) = params.max_tokens {
+        parts.push(format!("max-tokens {max_tokens}"));
+    }
+    if let Some(seed) = params.seed {
+        parts.push(format!("seed {seed}"));
+    }
+    if !params.stop.is_empty() {
+        parts.push(format!("stop {:?}", params.stop));
     }
 
-    ratatui::restore();
+    if parts.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", parts.join(", "))
+    }
+}
 
-    Ok(())
+/// The settings with the provider and model given as flags, which only apply
+/// to this question and are never saved
+fn override_settings(args: &QuestionArgs, settings: &Settings) -> Settings {
+    let provider = args
+        .model
+        .as_ref()
+        .and_then(|choice| choice.provider)
+        .or(args.provider);
+    let model = args.model.as_ref().map(|choice| choice.model.as_str());
+
+    settings.with_model(provider, model)
 }
 
-fn validate_ai_settings(settings: &Settings) -> Result<()> {
-    if settings.provider.is_none() {
-        return Err(anyhow::anyhow!("AI provider is not set"));
+/// Splits the preset off the question. It is either given with `--preset`,
+/// or is the first word of the question if that is the exact name of a preset.
+fn take_preset<'a>(
+    args: &QuestionArgs,
+    settings: &'a Settings,
+) -> Result<(Option<(String, &'a Preset)>, String)> {
+    let presets = settings.presets.as_ref();
+    let find = |name: &str| presets.and_then(|presets| presets.get(name));
+    let words = &args.question;
+
+    if let Some(name) = &args.preset {
+        let preset = find(name).ok_or_else(|| anyhow::anyhow!("No preset found for '{name}'"))?;
+        return Ok((Some((name.clone(), preset)), words.join(" ")));
     }
 
-    if settings.model.is_none() {
-        return Err(anyhow::anyhow!("AI model is not set"));
+    if words.len() < 2 || args.literal {
+        return Ok((None, words.join(" ")));
     }
 
-    match settings.provider.unwrap() {
-        AIProvider::DeepSeek => {
-            if settings.deepseek_key.is_none() {
-                return Err(anyhow::anyhow!("DeepSeek API key is not set"));
-            }
+    let first = &words[0];
+    if let Some(preset) = find(first) {
+        return Ok((Some((first.clone(), preset)), words[1..].join(" ")));
+    }
+    if let Some(name) = presets
+        .into_iter()
+        .flat_map(|presets| presets.keys())
+        .find(|name| name.eq_ignore_ascii_case(first))
+    {
+        eprintln!(
+            "Warning: '{first}' is sent as part of the question, use `ask -P {name} ...` to use the preset '{name}'"
+        );
+    }
 
-            match settings.model.unwrap() {
-                AIModel::DeepSeekChat => {}
-                _ => {
-                    return Err(anyhow::anyhow!(
-                        "DeepSeek provider only supports deepseek-chat model"
-                    ));
-                }
+    Ok((None, words.join(" ")))
+}
+
+fn handle_question(args: &QuestionArgs, settings: &Settings) -> Result<()> {
+    // The settings to ask each model with, several when comparing
+    let targets: Vec<Settings> = if args.compare.is_empty() {
+        vec![settings.clone()]
+    } else {
+        args.compare
+            .iter()
+            .map(|choice| settings.with_model(choice.provider, Some(&choice.model)))
+            .collect()
+    };
+    let clients = targets
+        .iter()
+        .map(Ask::from_settings)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (found_preset, question) = take_preset(args, settings)?;
+
+    // Flags win over the preset, which wins over the defaults
+    let params = args
+        .params
+        .clone()
+        .or(&found_preset
+            .as_ref()
+            .map(|(_, preset)| preset.params())
+            .unwrap_or_default())
+        .or(&settings.params.clone().unwrap_or_default());
+
+    dprintln!("params: {:?}", params);
+    if params.seed.is_some() {
+        for client in &clients {
+            if !ai::supports_seed(client.provider()) {
+                eprintln!(
+                    "{} does not support a seed, ignoring it",
+                    client.provider().name()
+                );
             }
         }
-        AIProvider::Grok => {
-            if settings.grok_key.is_none() {
-                return Err(anyhow::anyhow!("Grok API key is not set"));
-            }
-            match settings.model.unwrap() {
-                AIModel::Grok3 => {}
-                _ => {
-                    return Err(anyhow::anyhow!("Grok provider only supports Grok3 model"));
-                }
+    }
+
+    let image_urls = args
+        .images
+        .iter()
+        .map(|image| ai::image_url(image))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut messages = Vec::<Message>::new();
+    let preset = match found_preset {
+        Some((name, preset)) => {
+            if !preset.prompt().trim().is_empty() {
+                messages.push(Message::user(preset.prompt().trim()));
             }
+            Some(name)
+        }
+        None => None,
+    };
+    let excerpts = match &args.docs {
+        Some(dir) => {
+            // The first model embeds, it is the one of the settings unless comparing
+            let (client, target) = (&clients[0], &targets[0]);
+            let embedder = args
+                .embeddings
+                .then(|| {
+                    let provider = client.provider();
+                    let embedder = target.api_key(provider).map(|api_key| docs::Embedder {
+                        provider,
+                        api_key,
+                        http: target.http_options_for(provider),
+                    });
+                    embedder.ok_or_else(|| AskError::missing_key(provider))
+                })
+                .transpose()?;
+            docs::retrieve(dir, question.trim(), embedder.as_ref())
+                .with_context(|| format!("Failed to search {}", dir.display()))?
+        }
+        None => Vec::new(),
+    };
+    if !excerpts.is_empty() {
+        messages.push(Message::user(docs::prompt(&excerpts)));
+    }
+    messages.push(Message::user_with_images(question.trim(), image_urls));
+
+    dprintln!("messages: {:?}", messages);
+
+    // The views show which images were sent along with the question
+    let mut shown_question = question.clone();
+    for image in &args.images {
+        let name = if image.starts_with("data:") {
+            "data URL"
+        } else {
+            image
+        };
+        shown_question.push_str(&format!("\n\n[image: {name}]"));
+    }
+    // The numbers the answer cites
+    let sources: Vec<String> = excerpts
+        .iter()
+        .enumerate()
+        .map(|(number, excerpt)| format!("[{}] {}", number + 1, excerpt.source()))
+        .collect();
+    if !sources.is_empty() {
+        shown_question.push('\n');
+        for source in &sources {
+            shown_question.push_str(&format!("\n- {source}"));
         }
-        AIProvider::Qwen => {
-            if settings.qwen_key.is_none() {
-                return Err(anyhow::anyhow!("Qwen API key is not set"));
+    }
+    if args.docs.is_some() && excerpts.is_empty() {
+        eprintln!("No matching excerpts found in the documents");
+    }
+
+    stdout().flush().unwrap();
+
+    // Answers using tools depend on more than the question, so they aren't cached
+    let cache =
+        cache::ResponseCache::from_settings(settings).filter(|_| !args.no_cache && !args.tools);
+
+    let mut answers = Vec::new();
+    let mut cached_labels = Vec::new();
+    for (target, client) in targets.iter().zip(&clients) {
+        let model = client.model();
+        let label = format!("{}:{model}", target.provider.unwrap());
+        let key = cache::ResponseCache::key(
+            &target.provider.unwrap().to_string(),
+            model,
+            &messages,
+            &params,
+        );
+        let replay = match &cache {
+            Some(cache) if !args.refresh => cache.replay(&key),
+            _ => None,
+        };
+
+        let events = if let Some(events) = replay {
+            cached_labels.push(label.clone());
+            events
+        } else if args.tools {
+            let (params, client) = (params.clone(), client.clone());
+            tools::start(messages.clone(), settings, move |messages, definitions| {
+                Ok(client.start(messages, &params, definitions)?)
+            })
+        } else {
+            let events = client.start_async(&messages, &params, &[])?;
+            match &cache {
+                Some(cache) => cache.record(key, events),
+                None => events,
             }
+        };
+        answers.push((label, events, target.price(model)));
+    }
+
+    // Piped output never gets the TUI, and only gets colours on a terminal
+    let is_terminal = stdout().is_terminal();
+    let output = settings.output.unwrap_or_default();
+    let plain = output == OutputMode::Plain || !is_terminal;
+    let keep_answer = args.keep_answer || settings.keep_answer.unwrap_or(false);
+    // Without the TUI the sources go to stderr, so stdout is just the answer
+    if plain || args.jsonl {
+        for source in &sources {
+            eprintln!("{source}");
+        }
+        for label in &cached_labels {
+            eprintln!("Cached answer from {label}, use --refresh to ask again");
+        }
+    }
+
+    let usages = if args.jsonl {
+        plain::print_jsonl(
+            answers
+                .into_iter()
+                .map(|(label, events, _)| (label, events))
+                .collect(),
+        )?
+    } else {
+        // The views read the answers from channels, filled on the runtime
+        let mut streams: Vec<compare::Stream> = answers
+            .into_iter()
+            .map(|(label, events, price)| {
+                let (rx, cancel) = ai::forward(events);
+                compare::Stream {
+                    label,
+                    rx,
+                    cancel,
+                    price,
+                }
+            })
+            .collect();
 
-            match settings.model.unwrap() {
-                AIModel::QwenPlus | AIModel::QwenFlash => {}
-                _ => {
-                    return Err(anyhow::anyhow!(
-                        "Qwen provider only supports QwenPlus and QwenFlash models"
-                    ));
+        if args.compare.is_empty() {
+            let stream = streams.remove(0);
+            let usage = if plain {
+                plain::print_reply(stream.rx, is_terminal, args.show_reasoning)?
+            } else {
+                let view = tui::ViewOptions {
+                    inline: output == OutputMode::Inline,
+                    keep_answer,
+                    price: stream.price,
+                };
+                tui::handle_reply(&shown_question, stream.rx, stream.cancel, view)?
+            };
+            vec![usage]
+        } else if plain {
+            // One answer after the other, the rest keep streaming in meanwhile
+            let mut usages = Vec::new();
+            let mut failures = Vec::new();
+            for stream in streams {
+                println!("## {}\n", stream.label);
+                match plain::print_reply(stream.rx, is_terminal, args.show_reasoning) {
+                    std::result::Result::Ok(usage) => usages.push(usage),
+                    Err(err) => {
+                        eprintln!("{}: {err:#}", stream.label);
+                        failures.push(err);
+                        usages.push(None);
+                    }
                 }
+                println!();
             }
+            // Only an error if no model answered
+            if failures.len() == usages.len()
+                && let Some(err) = failures.into_iter().next()
+            {
+                return Err(err);
+            }
+            usages
+        } else {
+            compare::handle_replies(&shown_question, streams, keep_answer)?
+        }
+    };
+
+    for (target, usage) in targets.iter().zip(usages) {
+        let Some(usage) = usage else {
+            continue;
+        };
+        let record = usage::UsageRecord::new(
+            &target.provider.unwrap().to_string(),
+            target.model.as_deref().unwrap(),
+            preset.as_deref(),
+            usage,
+        );
+        // The answer is already shown, a usage log that can't be written
+        // doesn't make the question fail
+        if let Err(err) = usage::record(&record) {
+            eprintln!("Failed to record usage: {err:#}");
         }
     }
 
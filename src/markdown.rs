@@ -0,0 +1,246 @@
+use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag, TagEnd};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span, Text};
+use std::ops::Range;
+use std::sync::LazyLock;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{FontStyle, Theme, ThemeSet};
+use syntect::parsing::SyntaxSet;
+use syntect::util::{LinesWithEndings, as_24_bit_terminal_escaped};
+
+/// Bundled syntect theme used for code blocks
+static THEME_NAME: &str = "base16-ocean.dark";
+
+static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+static THEME: LazyLock<Theme> = LazyLock::new(|| {
+    ThemeSet::load_defaults()
+        .themes
+        .remove(THEME_NAME)
+        .unwrap_or_default()
+});
+
+/// A fenced code block found in an answer
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodeBlock {
+    /// Language from the fence info string, empty if not given
+    pub lang: String,
+    pub code: String,
+    /// Byte range of the whole block, fences included, in the source
+    pub range: Range<usize>,
+    /// Inside a list item or a block quote, which is rendered with it
+    pub nested: bool,
+}
+
+/// Collects the fenced code blocks of a markdown document in order of appearance.
+///
+/// A block that is still streaming (no closing fence yet) is included.
+pub fn code_blocks(md: &str) -> Vec<CodeBlock> {
+    let mut blocks = Vec::new();
+    let mut current: Option<CodeBlock> = None;
+    // List items and block quotes the parser is in
+    let mut containers = 0;
+
+    for (event, range) in Parser::new(md).into_offset_iter() {
+        match event {
+            Event::Start(Tag::Item | Tag::BlockQuote(_)) => containers += 1,
+            Event::End(TagEnd::Item | TagEnd::BlockQuote(_)) => containers -= 1,
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => {
+                current = Some(CodeBlock {
+                    lang: fence_lang(&info).to_owned(),
+                    code: String::new(),
+                    range,
+                    nested: containers > 0,
+                });
+            }
+            Event::Text(text) => {
+                if let Some(block) = &mut current {
+                    block.code.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                blocks.extend(current.take());
+            }
+            _ => {}
+        }
+    }
+
+    blocks
+}
+
+/// The language is the first word of the info string, e.g. `rust` in "```rust ignore"
+pub fn fence_lang(info: &str) -> &str {
+    info.split([' ', ',', '{'])
+        .next()
+        .unwrap_or_default()
+        .trim()
+}
+
+/// Whether a line opens or closes a fenced code block
+pub fn is_fence(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with("```") || line.starts_with("~~~")
+}
+
+/// Highlights a code block line by line, keeping the parser state between lines
+pub struct CodeHighlighter {
+    lines: HighlightLines<'static>,
+}
+
+impl CodeHighlighter {
+    /// Unknown languages fall back to plain text
+    pub fn new(lang: &str) -> Self {
+        let syntax = SYNTAXES
+            .find_syntax_by_token(lang)
+            .unwrap_or_else(|| SYNTAXES.find_syntax_plain_text());
+
+        Self {
+            lines: HighlightLines::new(syntax, &THEME),
+        }
+    }
+
+    /// Highlights one line, which should include its line ending
+    pub fn highlight_spans(&mut self, line: &str) -> Vec<Span<'static>> {
+        let Ok(ranges) = self.lines.highlight_line(line, &SYNTAXES) else {
+            return vec![Span::from(line.trim_end_matches('\n').to_owned())];
+        };
+
+        ranges
+            .into_iter()
+            .map(|(style, text)| {
+                let mut modifier = Modifier::empty();
+                if style.font_style.contains(FontStyle::BOLD) {
+                    modifier |= Modifier::BOLD;
+                }
+                if style.font_style.contains(FontStyle::ITALIC) {
+                    modifier |= Modifier::ITALIC;
+                }
+                if style.font_style.contains(FontStyle::UNDERLINE) {
+                    modifier |= Modifier::UNDERLINED;
+                }
+                // The terminal background is kept, only the foreground is themed
+                let fg = Color::Rgb(style.foreground.r, style.foreground.g, style.foreground.b);
+                Span::styled(
+                    text.trim_end_matches('\n').to_owned(),
+                    Style::new().fg(fg).add_modifier(modifier),
+                )
+            })
+            .filter(|span| !span.content.is_empty())
+            .collect()
+    }
+
+    /// Highlights one line with ANSI escape codes for printing to a terminal
+    pub fn highlight_ansi(&mut self, line: &str) -> String {
+        let Ok(ranges) = self.lines.highlight_line(line, &SYNTAXES) else {
+            return line.to_owned();
+        };
+        // Reset the colours before the line break, not after it
+        let ending = if line.ends_with('\n') { "\n" } else { "" };
+        format!(
+            "{}\x1b[0m{ending}",
+            as_24_bit_terminal_escaped(&ranges, false).trim_end_matches('\n')
+        )
+    }
+}
+
+/// Renders markdown for the answer view, highlighting fenced code blocks.
+/// Blocks in a list item or a block quote are left to their container, which
+/// would break apart otherwise.
+///
+/// `focused` is the index of a code block to mark as selected.
+pub fn render(md: &str, focused: Option<usize>) -> Text<'static> {
+    let mut text = Text::default();
+    let mut prose_start = 0;
+
+    for (index, block) in code_blocks(md).iter().enumerate() {
+        if block.nested {
+            continue;
+        }
+        push_prose(&mut text, &md[prose_start..block.range.start]);
+        push_code(&mut text, block, focused == Some(index));
+        prose_start = block.range.end;
+    }
+    push_prose(&mut text, &md[prose_start..]);
+
+    text
+}
+
+fn push_prose(text: &mut Text<'static>, md: &str) {
+    if md.trim().is_empty() {
+        return;
+    }
+    if !text.lines.is_empty() {
+        text.lines.push(Line::default());
+    }
+    text.lines
+        .extend(tui_markdown::from_str(md).lines.into_iter().map(into_owned));
+}
+
+fn push_code(text: &mut Text<'static>, block: &CodeBlock, focused: bool) {
+    if !text.lines.is_empty() {
+        text.lines.push(Line::default());
+    }
+
+    let fence_style = if focused {
+        Style::new().reversed()
+    } else {
+        Style::new().dark_gray()
+    };
+
+    text.lines
+        .push(Line::styled(format!("```{}", block.lang), fence_style));
+    let mut highlighter = CodeHighlighter::new(&block.lang);
+    for line in LinesWithEndings::from(&block.code) {
+        text.lines
+            .push(Line::from(highlighter.highlight_spans(line)));
+    }
+    text.lines.push(Line::styled("```", fence_style));
+}
+
+fn into_owned(line: Line<'_>) -> Line<'static> {
+    Line {
+        spans: line
+            .spans
+            .into_iter()
+            .map(|span| Span::styled(span.content.into_owned(), span.style))
+            .collect(),
+        style: line.style,
+        alignment: line.alignment,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(text: &Text) -> Vec<String> {
+        text.lines
+            .iter()
+            .map(|line| {
+                line.spans
+                    .iter()
+                    .map(|span| span.content.as_ref())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn leaves_code_in_lists_and_quotes_to_them() {
+        let list = "1. step\n   ```sh\n   ls\n   ```\n   then\n2. step\n";
+        assert!(code_blocks(list)[0].nested);
+        // The list is rendered in one piece, not split at the fence
+        assert_eq!(
+            plain(&render(list, None)),
+            plain(&tui_markdown::from_str(list))
+        );
+
+        let quote = "> quote\n> ```sh\n> ls\n> ```\n> after\n\n```rust\nfn main() {}\n```\n";
+        let blocks = code_blocks(quote);
+        assert!(blocks[0].nested);
+        assert!(!blocks[1].nested);
+        let lines = plain(&render(quote, None));
+        assert!(lines.contains(&"> ```sh".to_owned()), "{lines:?}");
+        // Blocks at the top level still get fences and highlighting of their own
+        assert!(lines.contains(&"```rust".to_owned()), "{lines:?}");
+    }
+}
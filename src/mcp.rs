@@ -0,0 +1,307 @@
+use anyhow::{Context, Result};
+use serde_json::{Value, json};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use crate::ai::ToolDefinition;
+use crate::settings::McpServer;
+
+/// Protocol version sent in the handshake, servers answer with the one they speak
+static PROTOCOL_VERSION: &str = "2025-06-18";
+/// Separates the server name from the tool name in the names the model sees
+pub static SEPARATOR: &str = "__";
+/// Longest tool name the providers accept
+static MAX_NAME_LEN: usize = 64;
+/// How long a server may take to answer a request
+static REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A running MCP server, spoken to with JSON-RPC messages, one per line on
+/// its stdin and stdout
+pub struct McpClient {
+    pub name: String,
+    child: Child,
+    stdin: ChildStdin,
+    /// Lines the server writes, read in a thread so requests can time out
+    lines: Receiver<String>,
+    next_id: u64,
+}
+
+impl McpClient {
+    /// Starts the server and does the initialization handshake
+    pub fn start(name: &str, server: &McpServer) -> Result<Self> {
+        // The server's log output would end up in the middle of the answer
+        let mut child = Command::new(&server.command)
+            .args(&server.args)
+            .envs(&server.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| format!("Failed to run {}", server.command))?;
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let (tx, lines) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else {
+                    return;
+                };
+                if tx.send(line).is_err() {
+                    return;
+                }
+            }
+        });
+
+        let mut client = Self {
+            name: name.to_owned(),
+            child,
+            stdin,
+            lines,
+            next_id: 1,
+        };
+        client.request(
+            "initialize",
+            json!({
+                "protocolVersion": PROTOCOL_VERSION,
+                "capabilities": {},
+                "clientInfo": { "name": "ask", "version": env!("CARGO_PKG_VERSION") }
+            }),
+        )?;
+        client.send(&json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/initialized"
+        }))?;
+
+        Ok(client)
+    }
+
+    /// The tools of the server, named `{server}__{tool}` for the model, and
+    /// the names of the tools left out because the model couldn't call them
+    pub fn tools(&mut self) -> Result<(Vec<ToolDefinition>, Vec<String>)> {
+        let mut tools = Vec::new();
+        let mut skipped = Vec::new();
+        let mut cursor: Option<String> = None;
+
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let result = self.request("tools/list", params)?;
+            for tool in result["tools"].as_array().into_iter().flatten() {
+                let Some(name) = tool["name"].as_str() else {
+                    continue;
+                };
+                let full_name = format!("{}{SEPARATOR}{name}", self.name);
+                if !is_valid_name(&full_name) {
+                    skipped.push(name.to_owned());
+                    continue;
+                }
+                tools.push(ToolDefinition {
+                    name: full_name,
+                    description: tool["description"].as_str().unwrap_or_default().to_owned(),
+                    parameters: match &tool["inputSchema"] {
+                        Value::Null => json!({ "type": "object" }),
+                        schema => schema.clone(),
+                    },
+                });
+            }
+
+            cursor = result["nextCursor"].as_str().map(str::to_owned);
+            if cursor.is_none() {
+                return Ok((tools, skipped));
+            }
+        }
+    }
+
+    /// Calls a tool by its name on the server, without the server prefix.
+    /// The text of the result is returned, or the error if the tool failed.
+    pub fn call(&mut self, tool: &str, arguments: Value) -> Result<String> {
+        let result = self.request(
+            "tools/call",
+            json!({ "name": tool, "arguments": arguments }),
+        )?;
+
+        let mut output = Vec::new();
+        for content in result["content"].as_array().into_iter().flatten() {
+            match content["type"].as_str() {
+                Some("text") => {
+                    output.push(content["text"].as_str().unwrap_or_default().to_owned())
+                }
+                Some("resource") => output.push(
+                    content["resource"]["text"]
+                        .as_str()
+                        .map(str::to_owned)
+                        .unwrap_or_else(|| format!("[resource {}]", content["resource"]["uri"])),
+                ),
+                Some("resource_link") => output.push(format!("[resource {}]", content["uri"])),
+                Some(kind) => output.push(format!("[{kind} content]")),
+                None => {}
+            }
+        }
+        // Newer servers may only fill in the structured result
+        if output.is_empty() && !result["structuredContent"].is_null() {
+            output.push(result["structuredContent"].to_string());
+        }
+        let output = output.join("\n");
+
+        if result["isError"].as_bool().unwrap_or(false) {
+            return Err(anyhow::anyhow!(output));
+        }
+        Ok(output)
+    }
+
+    fn send(&mut self, message: &Value) -> Result<()> {
+        writeln!(self.stdin, "{message}")?;
+        self.stdin.flush()?;
+        Ok(())
+    }
+
+    /// Sends a request and waits for its response, skipping the
+    /// notifications the server sends meanwhile
+    fn request(&mut self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(&json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))
+            .with_context(|| format!("MCP server {} is not running", self.name))?;
+
+        loop {
+            let line = match self.lines.recv_timeout(REQUEST_TIMEOUT) {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(anyhow::anyhow!(
+                        "MCP server {} did not answer {method} within {} seconds",
+                        self.name,
+                        REQUEST_TIMEOUT.as_secs()
+                    ));
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(anyhow::anyhow!("MCP server {} exited", self.name));
+                }
+            };
+            let Ok(message) = serde_json::from_str::<Value>(&line) else {
+                continue;
+            };
+
+            // Requests from the server, e.g. for sampling, aren't supported
+            if message["method"].is_string() {
+                if !message["id"].is_null() {
+                    self.send(&json!({
+                        "jsonrpc": "2.0",
+                        "id": message["id"],
+                        "error": { "code": -32601, "message": "Method not found" }
+                    }))?;
+                }
+                continue;
+            }
+            if message["id"].as_u64() != Some(id) {
+                continue;
+            }
+
+            if let Some(error) = message.get("error") {
+                return Err(anyhow::anyhow!(
+                    "MCP server {} failed {method}: {}",
+                    self.name,
+                    error["message"].as_str().unwrap_or("unknown error")
+                ));
+            }
+            return Ok(message["result"].clone());
+        }
+    }
+}
+
+/// Whether the providers accept the name for a tool, they allow
+/// `^[a-zA-Z0-9_-]{1,64}$`
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl Drop for McpClient {
+    fn drop(&mut self) {
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stand-in MCP server. It lists its tools on two pages, and asks the
+    /// client for sampling before the first page, which it only sends once
+    /// the client has refused.
+    static SERVER: &str = r#"
+reply() { printf '{"jsonrpc":"2.0","id":%s,"result":%s}\n' "$id" "$1"; }
+while read -r line; do
+    id=$(printf '%s' "$line" | sed -n 's/.*"id":\([0-9][0-9]*\).*/\1/p')
+    case "$line" in
+    *'"code":-32601'*)
+        id=$listing
+        reply '{"tools":[{"name":"echo","description":"Echoes","inputSchema":{"type":"object","properties":{}}},{"name":"bad.name"}],"nextCursor":"2"}' ;;
+    *'"initialize"'*)
+        reply '{"protocolVersion":"2025-06-18","capabilities":{"tools":{}},"serverInfo":{"name":"stub","version":"1"}}' ;;
+    *'"tools/list"'*'"cursor":"2"'*)
+        reply '{"tools":[{"name":"fail"},{"name":"a_tool_name_that_is_too_long_once_the_server_name_is_added_to_it"}]}' ;;
+    *'"tools/list"'*)
+        listing=$id
+        printf '{"jsonrpc":"2.0","id":"sampling","method":"sampling/createMessage","params":{}}\n' ;;
+    *'"tools/call"'*'"name":"fail"'*)
+        reply '{"content":[{"type":"text","text":"it broke"}],"isError":true}' ;;
+    *'"tools/call"'*)
+        reply '{"content":[{"type":"text","text":"echoed"}]}' ;;
+    esac
+done
+"#;
+
+    fn start() -> McpClient {
+        let server = McpServer {
+            command: "sh".to_owned(),
+            args: vec!["-c".to_owned(), SERVER.to_owned()],
+            env: Default::default(),
+        };
+        McpClient::start("stub", &server).unwrap()
+    }
+
+    #[test]
+    fn lists_the_tools_of_every_page() {
+        let mut client = start();
+        let (tools, skipped) = client.tools().unwrap();
+
+        let names: Vec<&str> = tools.iter().map(|tool| tool.name.as_str()).collect();
+        assert_eq!(names, ["stub__echo", "stub__fail"]);
+        assert_eq!(tools[0].description, "Echoes");
+        assert_eq!(tools[1].parameters, json!({ "type": "object" }));
+        assert_eq!(
+            skipped,
+            [
+                "bad.name",
+                "a_tool_name_that_is_too_long_once_the_server_name_is_added_to_it"
+            ]
+        );
+    }
+
+    #[test]
+    fn calls_tools() {
+        let mut client = start();
+        assert_eq!(client.call("echo", json!({})).unwrap(), "echoed");
+        let err = client.call("fail", json!({})).unwrap_err();
+        assert_eq!(err.to_string(), "it broke");
+    }
+
+    #[test]
+    fn tool_names() {
+        assert!(is_valid_name("server__read-file_2"));
+        assert!(is_valid_name(&"a".repeat(64)));
+        assert!(!is_valid_name(&"a".repeat(65)));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("server__read.file"));
+        assert!(!is_valid_name("server__読む"));
+    }
+}
@@ -0,0 +1,103 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+
+use crate::ai::{self, HttpOptions};
+use crate::command::AIProvider;
+use crate::settings::Settings;
+use crate::util::unix_now;
+
+/// File in the config directory caching the model lists of the providers
+static MODELS_FILE: &str = "ask_models.json";
+/// How long a fetched model list is used before it's fetched again, in seconds
+static MODELS_TTL: u64 = 24 * 60 * 60;
+
+/// Model lists by provider name
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ModelCache(HashMap<String, CachedModels>);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedModels {
+    /// Unix time in seconds
+    fetched: u64,
+    models: Vec<String>,
+}
+
+impl CachedModels {
+    fn is_fresh(&self) -> bool {
+        unix_now().saturating_sub(self.fetched) < MODELS_TTL
+    }
+}
+
+fn cache_path() -> PathBuf {
+    Settings::config_dir().join(MODELS_FILE)
+}
+
+/// A missing or broken cache is treated as empty
+fn load_cache() -> ModelCache {
+    std::fs::read_to_string(cache_path())
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn save_cache(cache: &ModelCache) -> Result<()> {
+    std::fs::write(cache_path(), serde_json::to_string_pretty(cache)?)?;
+    Ok(())
+}
+
+/// The cached models of a provider, however old, or the built-in ones
+pub fn cached(provider: AIProvider) -> Vec<String> {
+    match load_cache().0.remove(&provider.to_string()) {
+        Some(cached) => cached.models,
+        None => provider
+            .builtin_models()
+            .iter()
+            .map(|model| model.to_string())
+            .collect(),
+    }
+}
+
+/// Every model known for any provider, for completions
+pub fn known_models() -> Vec<String> {
+    AIProvider::value_variants()
+        .iter()
+        .flat_map(|provider| cached(*provider))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// The models of a provider, fetched from the provider if the cached list is
+/// missing, older than the TTL, or `refresh` is set
+pub fn list(
+    provider: AIProvider,
+    api_key: &str,
+    http: &HttpOptions,
+    refresh: bool,
+) -> Result<Vec<String>> {
+    let mut cache = load_cache();
+
+    if !refresh
+        && let Some(cached) = cache.0.get(&provider.to_string())
+        && cached.is_fresh()
+    {
+        return Ok(cached.models.clone());
+    }
+
+    let mut models = ai::models(provider, api_key, http)?;
+    models.sort();
+
+    cache.0.insert(
+        provider.to_string(),
+        CachedModels {
+            fetched: unix_now(),
+            models: models.clone(),
+        },
+    );
+    save_cache(&cache)?;
+
+    Ok(models)
+}
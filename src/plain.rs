@@ -0,0 +1,255 @@
+use anyhow::Result;
+use ask::AskError;
+use futures_util::{StreamExt, future, stream};
+use serde_json::json;
+use std::io::{Write, stdout};
+use std::sync::mpsc::Receiver;
+use std::time::Instant;
+
+use crate::ai::{self, EventStream, StreamEvent, Usage};
+use crate::markdown::{self, CodeHighlighter};
+use crate::tools;
+
+/// Prints the reply to stdout as it streams in, without the TUI.
+///
+/// With `color`, fenced code blocks are highlighted with ANSI escape codes.
+/// The reasoning of reasoning models is left out unless `show_reasoning`.
+/// Tool calls are confirmed on the terminal.
+/// Returns the token usage if the provider reported it.
+pub fn print_reply(
+    rx: Receiver<StreamEvent>,
+    color: bool,
+    show_reasoning: bool,
+) -> Result<Option<Usage>> {
+    // Not locked, requests started while printing may write debug output
+    let mut printer = Printer::new(stdout(), color);
+    let mut usage: Option<Usage> = None;
+
+    for event in rx {
+        match event {
+            StreamEvent::Reasoning(content) if show_reasoning => {
+                printer.push_reasoning(&content)?
+            }
+            StreamEvent::Reasoning(_) => {}
+            StreamEvent::Delta(content) => printer.push(&content)?,
+            StreamEvent::Usage(reported) => *usage.get_or_insert_default() += reported,
+            StreamEvent::ToolRequest(request) => {
+                // The question goes on a line of its own
+                printer.finish()?;
+                request
+                    .reply
+                    .send(tools::confirm_on_terminal(&request.call))
+                    .ok();
+            }
+            StreamEvent::ToolCalls(_) => {}
+            StreamEvent::Error(err) => {
+                printer.finish()?;
+                return Err(err.into());
+            }
+        }
+    }
+
+    printer.finish()?;
+    Ok(usage)
+}
+
+/// Prints the events of one or more replies as JSON lines while they stream
+/// in, each tagged with the model it came from. Every reply ends with a
+/// "done" event, which follows an "error" event if the request failed.
+/// Tool calls are printed as "tool_request" events and confirmed on the terminal.
+///
+/// Returns the token usage of each reply, in the order of the streams, or
+/// the error of the first reply if every one failed.
+pub fn print_jsonl(streams: Vec<(String, EventStream)>) -> Result<Vec<Option<Usage>>> {
+    let started = Instant::now();
+    let mut usages = vec![None; streams.len()];
+    let mut first_tokens = vec![None; streams.len()];
+    let mut errors: Vec<Option<AskError>> = streams.iter().map(|_| None).collect();
+    let mut labels = Vec::new();
+
+    // Merge the streams on the runtime, None marks the end of a stream
+    let mut tagged = Vec::new();
+    for (index, (label, events)) in streams.into_iter().enumerate() {
+        labels.push(label);
+        let end = stream::once(future::ready((index, None)));
+        tagged.push(
+            events
+                .map(move |event| (index, Some(event)))
+                .chain(end)
+                .boxed(),
+        );
+    }
+    let (merged, _cancel) = ai::forward(stream::select_all(tagged));
+
+    let mut out = stdout();
+    for (index, event) in merged {
+        let model = &labels[index];
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        if let Some(StreamEvent::Reasoning(_) | StreamEvent::Delta(_)) = &event {
+            first_tokens[index].get_or_insert(elapsed_ms);
+        }
+        let line = match event {
+            Some(StreamEvent::Reasoning(content)) => {
+                json!({ "model": model, "type": "reasoning", "content": content })
+            }
+            Some(StreamEvent::Delta(content)) => {
+                json!({ "model": model, "type": "delta", "content": content })
+            }
+            Some(StreamEvent::Usage(usage)) => {
+                *usages[index].get_or_insert_default() += usage;
+                json!({
+                    "model": model,
+                    "type": "usage",
+                    "prompt_tokens": usage.prompt_tokens,
+                    "completion_tokens": usage.completion_tokens,
+                    "total_tokens": usage.total_tokens,
+                })
+            }
+            Some(StreamEvent::ToolRequest(request)) => {
+                let line = json!({
+                    "model": model,
+                    "type": "tool_request",
+                    "name": request.call.function.name,
+                    "arguments": request.call.function.arguments,
+                });
+                writeln!(out, "{line}")?;
+                out.flush()?;
+                request
+                    .reply
+                    .send(tools::confirm_on_terminal(&request.call))
+                    .ok();
+                continue;
+            }
+            Some(StreamEvent::ToolCalls(_)) => continue,
+            Some(StreamEvent::Error(err)) => {
+                let mut line =
+                    json!({ "model": model, "type": "error", "message": err.to_string() });
+                if let Some(hint) = err.hint() {
+                    line["hint"] = json!(hint);
+                }
+                errors[index].get_or_insert(err);
+                line
+            }
+            None => json!({
+                "model": model,
+                "type": "done",
+                "elapsed_ms": elapsed_ms,
+                "first_token_ms": first_tokens[index],
+            }),
+        };
+        writeln!(out, "{line}")?;
+        out.flush()?;
+    }
+
+    if errors.iter().all(Option::is_some)
+        && let Some(Some(err)) = errors.into_iter().next()
+    {
+        return Err(err.into());
+    }
+    Ok(usages)
+}
+
+/// Prose is printed as soon as it arrives. Code lines are held back until
+/// they are complete, because the highlighter works on whole lines.
+struct Printer<W: Write> {
+    out: W,
+    color: bool,
+    /// The current, not yet complete line
+    line: String,
+    /// Bytes of `line` that were already printed
+    printed: usize,
+    /// Set while inside a fenced code block
+    code: Option<CodeHighlighter>,
+    /// Reasoning was printed and still needs to be separated from the answer
+    after_reasoning: bool,
+}
+
+impl<W: Write> Printer<W> {
+    fn new(out: W, color: bool) -> Self {
+        Self {
+            out,
+            color,
+            line: String::new(),
+            printed: 0,
+            code: None,
+            after_reasoning: false,
+        }
+    }
+
+    /// Reasoning is printed as is, dimmed on a terminal
+    fn push_reasoning(&mut self, content: &str) -> Result<()> {
+        if self.color {
+            write!(self.out, "\x1b[2m{content}\x1b[0m")?;
+        } else {
+            write!(self.out, "{content}")?;
+        }
+        self.after_reasoning = true;
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn push(&mut self, content: &str) -> Result<()> {
+        if std::mem::take(&mut self.after_reasoning) {
+            write!(self.out, "\n\n---\n\n")?;
+        }
+        for piece in content.split_inclusive('\n') {
+            self.line.push_str(piece);
+            if piece.ends_with('\n') {
+                self.print_line()?;
+            } else {
+                self.print_partial()?;
+            }
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn print_partial(&mut self) -> Result<()> {
+        // Wait until it's clear whether the line is a fence or code
+        if self.code.is_some() || (self.printed == 0 && could_be_fence(&self.line)) {
+            return Ok(());
+        }
+        write!(self.out, "{}", &self.line[self.printed..])?;
+        self.printed = self.line.len();
+        Ok(())
+    }
+
+    fn print_line(&mut self) -> Result<()> {
+        let line = std::mem::take(&mut self.line);
+        let printed = std::mem::take(&mut self.printed);
+
+        if printed == 0 && markdown::is_fence(&line) {
+            self.code = match self.code {
+                Some(_) => None,
+                None => Some(CodeHighlighter::new(markdown::fence_lang(
+                    line.trim_start().trim_start_matches(['`', '~']),
+                ))),
+            };
+            if self.color {
+                writeln!(self.out, "\x1b[2m{}\x1b[0m", line.trim_end())?;
+            } else {
+                write!(self.out, "{line}")?;
+            }
+        } else if let (Some(code), true) = (&mut self.code, self.color) {
+            write!(self.out, "{}", code.highlight_ansi(&line))?;
+        } else {
+            write!(self.out, "{}", &line[printed..])?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if !self.line.is_empty() {
+            self.line.push('\n');
+            self.print_line()?;
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+fn could_be_fence(partial: &str) -> bool {
+    let partial = partial.trim_start();
+    markdown::is_fence(partial) || "```".starts_with(partial) || "~~~".starts_with(partial)
+}
@@ -0,0 +1,886 @@
+use anyhow::{Context, Result};
+use ask::{Ask, AskError, dprintln};
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::str::FromStr;
+use std::sync::mpsc::Receiver;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::ai::{self, CancelHandle, Message, StreamEvent, Usage};
+use crate::cache::ResponseCache;
+use crate::command::{AIProvider, GenerationParams, ModelChoice};
+use crate::models;
+use crate::settings::{Preset, Settings};
+use crate::usage;
+
+/// The model name clients use for the configured provider and model
+static DEFAULT_MODEL: &str = "ask";
+/// Prefix of the model names that ask with a preset, e.g. `preset/rust`
+static PRESET_PREFIX: &str = "preset/";
+/// Largest request body accepted, images come as data URLs
+static MAX_BODY: usize = 32 * 1024 * 1024;
+
+/// The body of a chat completions request, the fields ask doesn't support
+/// are ignored
+#[derive(Debug, Deserialize)]
+struct ChatRequest {
+    model: Option<String>,
+    messages: Vec<Message>,
+    #[serde(default)]
+    stream: bool,
+    stream_options: Option<StreamOptions>,
+    temperature: Option<f64>,
+    top_p: Option<f64>,
+    max_tokens: Option<u64>,
+    /// The newer name of max_tokens
+    max_completion_tokens: Option<u64>,
+    seed: Option<u64>,
+    stop: Option<Stop>,
+    tools: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamOptions {
+    #[serde(default)]
+    include_usage: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Stop {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl ChatRequest {
+    fn params(&self) -> GenerationParams {
+        GenerationParams {
+            temperature: self.temperature,
+            top_p: self.top_p,
+            max_tokens: self.max_completion_tokens.or(self.max_tokens),
+            seed: self.seed,
+            stop: match &self.stop {
+                Some(Stop::One(stop)) => vec![stop.clone()],
+                Some(Stop::Many(stop)) => stop.clone(),
+                None => Vec::new(),
+            },
+        }
+    }
+}
+
+/// A parsed HTTP request
+struct Request {
+    method: String,
+    path: String,
+    host: Option<String>,
+    /// Only browsers send it, and only to other sites
+    origin: Option<String>,
+    content_type: Option<String>,
+    /// Whether the client asked not to get a cached answer
+    no_cache: bool,
+    body: Vec<u8>,
+}
+
+impl Request {
+    /// Keeps web pages from using the API keys. A page can't read the
+    /// answers of another site, but it can send a form or a plain text POST
+    /// without asking first, and with DNS rebinding a site name of its own
+    /// can point at this server.
+    fn check_origin(&self, port: u16) -> std::result::Result<(), HttpError> {
+        if self.origin.is_some() {
+            return Err(HttpError::new(
+                403,
+                "Requests from web pages are not allowed",
+            ));
+        }
+        // Rebinding needs a host name, addresses and localhost can't be rebound
+        let allowed = self.host.as_deref().is_some_and(|host| {
+            let (name, host_port) = match host.rsplit_once(':') {
+                Some((name, host_port)) if !name.ends_with(':') => (name, host_port),
+                _ => return false,
+            };
+            let name = name.trim_start_matches('[').trim_end_matches(']');
+            host_port == port.to_string()
+                && (name.eq_ignore_ascii_case("localhost") || name.parse::<IpAddr>().is_ok())
+        });
+        if !allowed {
+            return Err(HttpError::new(
+                403,
+                format!(
+                    "The host {} is not allowed",
+                    self.host.as_deref().unwrap_or("(none)")
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_json(&self) -> std::result::Result<(), HttpError> {
+        let is_json = self.content_type.as_deref().is_some_and(|content_type| {
+            let media_type = content_type.split(';').next().unwrap_or_default();
+            media_type.trim().eq_ignore_ascii_case("application/json")
+        });
+        if !is_json {
+            return Err(HttpError::new(
+                415,
+                "The Content-Type must be application/json",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// An error sent to the client as an OpenAI error object
+struct HttpError {
+    status: u16,
+    message: String,
+}
+
+impl HttpError {
+    fn new(status: u16, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    /// The status of a failed question: the errors of the provider keep
+    /// their meaning, the ones of the connection to it are a bad gateway
+    fn ask(err: &AskError) -> Self {
+        let status = match err {
+            AskError::Config { .. } | AskError::Cancelled | AskError::Other(_) => 500,
+            AskError::Auth { .. } => 401,
+            AskError::RateLimit { .. } => 429,
+            AskError::Quota { .. } => 402,
+            AskError::BadModel { .. } => 404,
+            AskError::ContentFilter { .. } => 400,
+            AskError::Timeout(_) => 504,
+            AskError::Network(_) | AskError::Api { .. } => 502,
+        };
+        let message = match err {
+            AskError::Config { message, hint } => format!("{message}. {hint}"),
+            err => format!("{err:#}"),
+        };
+        Self::new(status, message)
+    }
+
+    /// The OpenAI error object
+    fn body(&self) -> Value {
+        let kind = match self.status {
+            401 => "authentication_error",
+            402 => "insufficient_quota",
+            429 => "rate_limit_error",
+            400..500 => "invalid_request_error",
+            502 | 504 => "upstream_error",
+            _ => "server_error",
+        };
+        json!({ "error": { "message": self.message, "type": kind } })
+    }
+}
+
+/// Serves `/v1/chat/completions` and `/v1/models` until the process is stopped.
+/// Every request is answered with the settings, presets, cache and API keys
+/// of ask, so the clients need no keys of their own.
+pub fn run(host: &str, port: u16, settings: &Settings) -> Result<()> {
+    let listener = TcpListener::bind((host, port))
+        .with_context(|| format!("Failed to listen on {host}:{port}"))?;
+    eprintln!(
+        "Serving an OpenAI-compatible API on http://{}/v1",
+        listener.local_addr()?
+    );
+    serve(listener, settings)
+}
+
+fn serve(listener: TcpListener, settings: &Settings) -> Result<()> {
+    let address = listener.local_addr()?;
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let settings = settings.clone();
+        std::thread::spawn(move || {
+            // Clients hanging up early are only reported in debug builds
+            if let Err(_err) = handle_connection(stream, address.port(), &settings) {
+                dprintln!("connection failed: {_err:#}");
+            }
+        });
+    }
+
+    Ok(())
+}
+
+fn handle_connection(mut stream: TcpStream, port: u16, settings: &Settings) -> Result<()> {
+    let request = match read_request(&stream)? {
+        Ok(request) => request,
+        Err(err) => return send_error(&mut stream, &err),
+    };
+    if let Err(err) = request.check_origin(port) {
+        eprintln!("{} {}: {}", request.method, request.path, err.message);
+        return send_error(&mut stream, &err);
+    }
+    let path = request.path.split('?').next().unwrap_or_default();
+
+    let result = match (request.method.as_str(), path) {
+        ("GET", "/v1/models") => send_json(&mut stream, 200, &list_models(settings)),
+        ("POST", "/v1/chat/completions") => match chat(&mut stream, &request, settings) {
+            Ok(()) => Ok(()),
+            Err(err) => send_error(&mut stream, &err),
+        },
+        (_, "/v1/models" | "/v1/chat/completions") => send_error(
+            &mut stream,
+            &HttpError::new(405, format!("{} is not allowed", request.method)),
+        ),
+        _ => send_error(
+            &mut stream,
+            &HttpError::new(404, format!("Unknown path {path}")),
+        ),
+    };
+    eprintln!("{} {path}", request.method);
+    result
+}
+
+/// Reads the request line, the headers and the body
+fn read_request(stream: &TcpStream) -> Result<std::result::Result<Request, HttpError>> {
+    let mut reader = BufReader::new(stream);
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Ok(Err(HttpError::new(400, "Invalid request line")));
+    };
+    let (method, path) = (method.to_owned(), path.to_owned());
+
+    let mut content_length = 0;
+    let mut no_cache = false;
+    let (mut host, mut origin, mut content_type) = (None, None, None);
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => content_length = value.parse().unwrap_or_default(),
+            "transfer-encoding" if !value.eq_ignore_ascii_case("identity") => {
+                return Ok(Err(HttpError::new(411, "Content-Length is required")));
+            }
+            "cache-control" => no_cache = value.to_ascii_lowercase().contains("no-cache"),
+            "host" => host = Some(value.to_owned()),
+            "origin" => origin = Some(value.to_owned()),
+            "content-type" => content_type = Some(value.to_owned()),
+            _ => {}
+        }
+    }
+    if content_length > MAX_BODY {
+        return Ok(Err(HttpError::new(413, "The request is too large")));
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    Ok(Ok(Request {
+        method,
+        path,
+        host,
+        origin,
+        content_type,
+        no_cache,
+        body,
+    }))
+}
+
+/// The models clients can choose: the configured one, the presets and the
+/// models of the providers with an API key
+fn list_models(settings: &Settings) -> Value {
+    let mut ids = vec![DEFAULT_MODEL.to_owned()];
+    let mut presets: Vec<&String> = settings.presets.iter().flat_map(|p| p.keys()).collect();
+    presets.sort();
+    ids.extend(presets.iter().map(|name| format!("{PRESET_PREFIX}{name}")));
+    for provider in AIProvider::value_variants() {
+        if settings.api_key(*provider).is_some() {
+            ids.extend(
+                models::cached(*provider)
+                    .iter()
+                    .map(|model| format!("{provider}:{model}")),
+            );
+        }
+    }
+
+    json!({
+        "object": "list",
+        "data": ids
+            .iter()
+            .map(|id| json!({ "id": id, "object": "model", "owned_by": "ask" }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// A preset and its name
+type NamedPreset = (String, Preset);
+
+/// The clients to answer with, in the order they are tried, and the preset,
+/// from the model the client chose. The fallback models only stand in for
+/// the configured one, a model the client chose is the only one asked.
+fn resolve_model(
+    model: Option<&str>,
+    settings: &Settings,
+) -> std::result::Result<(Vec<Ask>, Option<NamedPreset>), HttpError> {
+    // Whether the client chose the provider, and not the settings
+    let mut chosen = false;
+    let (target, preset) = match model {
+        None | Some("") => (settings.clone(), None),
+        Some(model) if model == DEFAULT_MODEL => (settings.clone(), None),
+        Some(model) if let Some(name) = model.strip_prefix(PRESET_PREFIX) => {
+            let preset = settings
+                .presets
+                .as_ref()
+                .and_then(|presets| presets.get(name))
+                .ok_or_else(|| HttpError::new(404, format!("No preset found for '{name}'")))?;
+            (settings.clone(), Some((name.to_owned(), preset.clone())))
+        }
+        Some(model) => {
+            let choice = ModelChoice::from_str(model)
+                .map_err(|err| HttpError::new(400, format!("Invalid model '{model}': {err}")))?;
+            chosen = true;
+            (
+                settings.with_model(choice.provider, Some(&choice.model)),
+                None,
+            )
+        }
+    };
+
+    let mut targets = vec![target];
+    if !chosen {
+        for model in settings.fallback.iter().flatten() {
+            match ModelChoice::from_str(model) {
+                Ok(choice) => {
+                    targets.push(settings.with_model(choice.provider, Some(&choice.model)))
+                }
+                Err(err) => eprintln!("Ignoring the fallback model '{model}': {err}"),
+            }
+        }
+    }
+
+    // A fallback without an API key is skipped, the first error is only
+    // sent when no model is left
+    let mut clients = Vec::new();
+    let mut failure = None;
+    for target in &targets {
+        match Ask::from_settings(target) {
+            Ok(client) => clients.push(client),
+            Err(err) => {
+                failure.get_or_insert(err);
+            }
+        }
+    }
+    if clients.is_empty()
+        && let Some(err) = failure
+    {
+        let mut err = HttpError::ask(&err);
+        // A provider without a key is the client's mistake if it chose it
+        if chosen && err.status == 500 {
+            err.status = 400;
+        }
+        return Err(err);
+    }
+    Ok((clients, preset))
+}
+
+/// Whether another model may answer after the error. The question itself
+/// is rejected by a content filter, and a cancelled one needs no answer.
+fn falls_back(err: &AskError) -> bool {
+    !matches!(
+        err,
+        AskError::ContentFilter { .. } | AskError::Cancelled | AskError::Other(_)
+    )
+}
+
+/// Starts the answer of one model, or replays it from the cache, and waits
+/// for its first event
+fn begin(
+    client: &Ask,
+    messages: &[Message],
+    params: &GenerationParams,
+    cache: Option<&ResponseCache>,
+    no_cache: bool,
+) -> std::result::Result<(Receiver<StreamEvent>, CancelHandle, Option<StreamEvent>), AskError> {
+    if params.seed.is_some() && !ai::supports_seed(client.provider()) {
+        eprintln!(
+            "{} does not support a seed, ignoring it",
+            client.provider().name()
+        );
+    }
+
+    let key = ResponseCache::key(
+        &client.provider().to_string(),
+        client.model(),
+        messages,
+        params,
+    );
+    let replay = match cache {
+        Some(cache) if !no_cache => cache.replay(&key),
+        _ => None,
+    };
+    let events = match replay {
+        Some(events) => events,
+        None => {
+            let events = client.start_async(messages, params, &[])?;
+            match cache {
+                Some(cache) => cache.record(key, events),
+                None => events,
+            }
+        }
+    };
+    let (rx, cancel) = ai::forward(events);
+
+    match rx.recv() {
+        Ok(StreamEvent::Error(err)) => Err(err),
+        Ok(event) => Ok((rx, cancel, Some(event))),
+        Err(_) => Ok((rx, cancel, None)),
+    }
+}
+
+fn chat(
+    stream: &mut TcpStream,
+    request: &Request,
+    settings: &Settings,
+) -> std::result::Result<(), HttpError> {
+    request.check_json()?;
+    let chat: ChatRequest = serde_json::from_slice(&request.body)
+        .map_err(|err| HttpError::new(400, format!("Invalid request: {err}")))?;
+    if chat.tools.is_some() {
+        return Err(HttpError::new(400, "Tools are not supported"));
+    }
+    if chat.messages.is_empty() {
+        return Err(HttpError::new(400, "The messages are missing"));
+    }
+
+    let (clients, preset) = resolve_model(chat.model.as_deref(), settings)?;
+
+    // Like on the command line: the request wins over the preset, which
+    // wins over the defaults
+    let mut messages = Vec::new();
+    let mut params = chat.params();
+    if let Some((_, preset)) = &preset {
+        if !preset.prompt().trim().is_empty() {
+            messages.push(Message::user(preset.prompt().trim()));
+        }
+        params = params.or(&preset.params());
+    }
+    params = params.or(&settings.params.clone().unwrap_or_default());
+    messages.extend(chat.messages.iter().cloned());
+
+    // Errors before the answer starts get a status code of their own, or
+    // the next model answers instead
+    let cache = ResponseCache::from_settings(settings);
+    let mut clients = clients.iter().peekable();
+    let (client, rx, cancel, first) = loop {
+        let Some(client) = clients.next() else {
+            unreachable!("there is at least one client");
+        };
+        match begin(client, &messages, &params, cache.as_ref(), request.no_cache) {
+            Ok((rx, cancel, first)) => break (client, rx, cancel, first),
+            Err(err) => match clients.peek() {
+                Some(next) if falls_back(&err) => eprintln!(
+                    "{err:#}, answering with {}:{} instead",
+                    next.provider(),
+                    next.model()
+                ),
+                _ => return Err(HttpError::ask(&err)),
+            },
+        }
+    };
+    let provider = client.provider().to_string();
+    let model = client.model().to_owned();
+    let completion = Completion::new(&model);
+    let usage = if chat.stream {
+        let include_usage = chat
+            .stream_options
+            .as_ref()
+            .is_some_and(|options| options.include_usage);
+        completion.stream(stream, first, rx, include_usage)
+    } else {
+        completion.collect(stream, first, rx)
+    };
+    let usage = match usage {
+        Ok(usage) => usage,
+        Err(_err) => {
+            // The client is gone, the provider can stop generating
+            cancel.cancel();
+            dprintln!("answer not sent: {_err:#}");
+            return Ok(());
+        }
+    };
+
+    if let Some(usage) = usage {
+        let record = usage::UsageRecord::new(
+            &provider,
+            &model,
+            preset.as_ref().map(|(name, _)| name.as_str()),
+            usage,
+        );
+        if let Err(err) = usage::record(&record) {
+            eprintln!("Failed to record usage: {err:#}");
+        }
+    }
+    Ok(())
+}
+
+/// Writes an answer in the OpenAI chat completion format
+struct Completion {
+    id: String,
+    created: u64,
+    model: String,
+}
+
+impl Completion {
+    fn new(model: &str) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Self {
+            id: format!("chatcmpl-ask-{:x}", now.as_nanos()),
+            created: now.as_secs(),
+            model: model.to_owned(),
+        }
+    }
+
+    fn chunk(&self, choices: Value) -> Value {
+        json!({
+            "id": self.id,
+            "object": "chat.completion.chunk",
+            "created": self.created,
+            "model": self.model,
+            "choices": choices,
+        })
+    }
+
+    /// Sends the answer as server-sent events while it comes in
+    fn stream(
+        &self,
+        stream: &mut TcpStream,
+        first: Option<StreamEvent>,
+        rx: Receiver<StreamEvent>,
+        include_usage: bool,
+    ) -> Result<Option<Usage>> {
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n"
+        )?;
+        let mut send = |event: &Value| -> Result<()> {
+            write!(stream, "data: {event}\n\n")?;
+            stream.flush()?;
+            Ok(())
+        };
+        let delta = |delta: Value| json!([{ "index": 0, "delta": delta, "finish_reason": null }]);
+
+        send(&self.chunk(delta(json!({ "role": "assistant", "content": "" }))))?;
+
+        let mut usage: Option<Usage> = None;
+        for event in first.into_iter().chain(rx) {
+            match event {
+                StreamEvent::Reasoning(text) => {
+                    send(&self.chunk(delta(json!({ "reasoning_content": text }))))?
+                }
+                StreamEvent::Delta(text) => send(&self.chunk(delta(json!({ "content": text }))))?,
+                StreamEvent::Usage(reported) => *usage.get_or_insert_default() += reported,
+                StreamEvent::Error(err) => {
+                    // The status is already sent, the error ends the stream instead of [DONE]
+                    send(&HttpError::ask(&err).body())?;
+                    return Ok(usage);
+                }
+                StreamEvent::ToolCalls(_) | StreamEvent::ToolRequest(_) => {}
+            }
+        }
+
+        send(&self.chunk(json!([{ "index": 0, "delta": {}, "finish_reason": "stop" }])))?;
+        if include_usage && let Some(usage) = usage {
+            let mut chunk = self.chunk(json!([]));
+            chunk["usage"] = usage_json(usage);
+            send(&chunk)?;
+        }
+        write!(stream, "data: [DONE]\n\n")?;
+        stream.flush()?;
+        Ok(usage)
+    }
+
+    /// Sends the whole answer once it is complete
+    fn collect(
+        &self,
+        stream: &mut TcpStream,
+        first: Option<StreamEvent>,
+        rx: Receiver<StreamEvent>,
+    ) -> Result<Option<Usage>> {
+        let mut reasoning = String::new();
+        let mut content = String::new();
+        let mut usage: Option<Usage> = None;
+        for event in first.into_iter().chain(rx) {
+            match event {
+                StreamEvent::Reasoning(text) => reasoning.push_str(&text),
+                StreamEvent::Delta(text) => content.push_str(&text),
+                StreamEvent::Usage(reported) => *usage.get_or_insert_default() += reported,
+                StreamEvent::Error(err) => {
+                    send_error(stream, &HttpError::ask(&err))?;
+                    return Ok(usage);
+                }
+                StreamEvent::ToolCalls(_) | StreamEvent::ToolRequest(_) => {}
+            }
+        }
+
+        let mut message = json!({ "role": "assistant", "content": content });
+        if !reasoning.is_empty() {
+            message["reasoning_content"] = json!(reasoning);
+        }
+        let mut body = json!({
+            "id": self.id,
+            "object": "chat.completion",
+            "created": self.created,
+            "model": self.model,
+            "choices": [{ "index": 0, "message": message, "finish_reason": "stop" }],
+        });
+        if let Some(usage) = usage {
+            body["usage"] = usage_json(usage);
+        }
+        send_json(stream, 200, &body)?;
+        Ok(usage)
+    }
+}
+
+fn usage_json(usage: Usage) -> Value {
+    json!({
+        "prompt_tokens": usage.prompt_tokens,
+        "completion_tokens": usage.completion_tokens,
+        "total_tokens": usage.total_tokens,
+    })
+}
+
+fn send_json(stream: &mut TcpStream, status: u16, body: &Value) -> Result<()> {
+    let body = body.to_string();
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        402 => "Payment Required",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        429 => "Too Many Requests",
+        502 => "Bad Gateway",
+        504 => "Gateway Timeout",
+        _ => "Internal Server Error",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status} {reason}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn send_error(stream: &mut TcpStream, err: &HttpError) -> Result<()> {
+    send_json(stream, err.status, &err.body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two chunks of an answer, like DeepSeek streams them
+    static ANSWER: &str = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nConnection: close\r\n\r\n\
+        data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hello\"}}]}\n\n\
+        data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\" world\"},\"finish_reason\":\"stop\"}]}\n\n\
+        data: [DONE]\n\n";
+
+    static RATE_LIMITED: &str = "HTTP/1.1 429 Too Many Requests\r\nContent-Type: application/json\r\nConnection: close\r\n\r\n\
+        {\"error\":{\"message\":\"Slow down\"}}";
+
+    /// Answers every request with the same response
+    fn upstream(response: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let request = read_request(&stream).unwrap().ok().unwrap();
+                assert_eq!(request.path, "/chat/completions");
+                let body: Value = serde_json::from_slice(&request.body).unwrap();
+                assert_eq!(body["stream"], true);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        format!("http://{address}")
+    }
+
+    /// Settings asking DeepSeek, which answers with the response
+    fn deepseek(response: &'static str) -> Value {
+        json!({
+            "provider": "deepseek",
+            "model": "deepseek-chat",
+            "deepseek_key": "test",
+            "deepseek_base_url": upstream(response),
+        })
+    }
+
+    fn start_server(settings: Value) -> u16 {
+        let settings: Settings = serde_json::from_value(settings).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || serve(listener, &settings));
+        port
+    }
+
+    /// Sends a request and returns the status with its reason and the body
+    fn post(port: u16, headers: &str, body: &Value) -> (String, String) {
+        let body = body.to_string();
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        write!(
+            stream,
+            "POST /v1/chat/completions HTTP/1.1\r\n{headers}Content-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.lines().next().unwrap();
+        let status = status.strip_prefix("HTTP/1.1 ").unwrap();
+        (status.to_owned(), body.to_owned())
+    }
+
+    fn question(stream: bool) -> Value {
+        json!({
+            "model": "ask",
+            "stream": stream,
+            "messages": [{ "role": "user", "content": "hi" }],
+        })
+    }
+
+    #[test]
+    fn streams_the_answer_as_events() {
+        let port = start_server(deepseek(ANSWER));
+        let headers = format!("Host: 127.0.0.1:{port}\r\nContent-Type: application/json\r\n");
+        let (status, body) = post(port, &headers, &question(true));
+        assert_eq!(status, "200 OK");
+
+        let events: Vec<&str> = body
+            .split("\n\n")
+            .filter_map(|event| event.strip_prefix("data: "))
+            .collect();
+        assert_eq!(events.last(), Some(&"[DONE]"));
+        let content: String = events
+            .iter()
+            .filter_map(|event| serde_json::from_str::<Value>(event).ok())
+            .filter_map(|chunk| {
+                chunk["choices"][0]["delta"]["content"]
+                    .as_str()
+                    .map(str::to_owned)
+            })
+            .collect();
+        assert_eq!(content, "Hello world");
+    }
+
+    #[test]
+    fn sends_the_whole_answer() {
+        let port = start_server(deepseek(ANSWER));
+        let headers = format!("Host: localhost:{port}\r\nContent-Type: application/json\r\n");
+        let (status, body) = post(port, &headers, &question(false));
+        assert_eq!(status, "200 OK");
+
+        let completion: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(completion["object"], "chat.completion");
+        assert_eq!(completion["model"], "deepseek-chat");
+        assert_eq!(
+            completion["choices"][0]["message"]["content"],
+            "Hello world"
+        );
+    }
+
+    #[test]
+    fn rejects_web_pages_and_other_hosts() {
+        let port = start_server(deepseek(ANSWER));
+        let json = "Content-Type: application/json\r\n";
+        let requests = [
+            (
+                format!("Host: 127.0.0.1:{port}\r\nOrigin: http://example.com\r\n{json}"),
+                "403 Forbidden",
+            ),
+            (
+                format!("Host: example.com:{port}\r\n{json}"),
+                "403 Forbidden",
+            ),
+            (
+                format!("Host: localhost:{}\r\n{json}", port.wrapping_add(1)),
+                "403 Forbidden",
+            ),
+            (json.to_owned(), "403 Forbidden"),
+            (
+                format!("Host: 127.0.0.1:{port}\r\nContent-Type: text/plain\r\n"),
+                "415 Unsupported Media Type",
+            ),
+        ];
+        for (headers, expected) in requests {
+            let (status, _) = post(port, &headers, &question(false));
+            assert_eq!(status, expected, "{headers}");
+        }
+    }
+
+    #[test]
+    fn passes_on_the_errors_of_the_provider() {
+        let port = start_server(deepseek(RATE_LIMITED));
+        let headers = format!("Host: localhost:{port}\r\nContent-Type: application/json\r\n");
+        let (status, body) = post(port, &headers, &question(false));
+        assert_eq!(status, "429 Too Many Requests");
+        let error: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(error["error"]["type"], "rate_limit_error");
+
+        // No Qwen key is set, which the client can't fix by retrying
+        let mut chosen = question(false);
+        chosen["model"] = json!("qwen:qwen-plus");
+        let (status, body) = post(port, &headers, &chosen);
+        assert_eq!(status, "400 Bad Request");
+        let error: Value = serde_json::from_str(&body).unwrap();
+        assert!(
+            error["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("ASK_QWEN_KEY")
+        );
+    }
+
+    #[test]
+    fn answers_with_the_fallback_model() {
+        let mut settings = deepseek(RATE_LIMITED);
+        settings["fallback"] = json!(["grok:grok-3", "qwen:qwen-plus"]);
+        settings["qwen_key"] = json!("test");
+        settings["qwen_base_url"] = json!(upstream(ANSWER));
+        let port = start_server(settings);
+        let headers = format!("Host: localhost:{port}\r\nContent-Type: application/json\r\n");
+
+        // Grok has no key and is skipped
+        let (status, body) = post(port, &headers, &question(false));
+        assert_eq!(status, "200 OK");
+        let completion: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(completion["model"], "qwen-plus");
+        assert_eq!(
+            completion["choices"][0]["message"]["content"],
+            "Hello world"
+        );
+
+        // A model the client chose is the only one asked
+        let mut chosen = question(false);
+        chosen["model"] = json!("deepseek:deepseek-chat");
+        let (status, _) = post(port, &headers, &chosen);
+        assert_eq!(status, "429 Too Many Requests");
+    }
+}
@@ -1,24 +1,148 @@
-use crate::command::{AIModel, AIProvider};
+use crate::ai::HttpOptions;
+use crate::command::{AIProvider, GenerationParams, OutputMode};
 use anyhow::Result;
 use config::{Config, FileFormat};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path;
 
-#[derive(Deserialize, Serialize, Debug)]
+/// Price of a model, per million tokens, in whatever currency the provider bills
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct ModelPrice {
+    /// Price per million prompt tokens
+    pub input: f64,
+    /// Price per million completion tokens
+    pub output: f64,
+}
+
+impl ModelPrice {
+    pub fn cost(&self, prompt_tokens: u64, completion_tokens: u64) -> f64 {
+        (prompt_tokens as f64 * self.input + completion_tokens as f64 * self.output) / 1_000_000.0
+    }
+}
+
+/// An MCP server that is started with `--tools` and talked to over stdio
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct McpServer {
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// Added to the environment ask runs in
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+}
+
+/// A prompt preset, stored as just the prompt unless it has its own
+/// generation parameters
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Preset {
+    Prompt(String),
+    WithParams {
+        prompt: String,
+        #[serde(flatten)]
+        params: GenerationParams,
+    },
+}
+
+impl Preset {
+    pub fn new(prompt: String, params: GenerationParams) -> Self {
+        if params.is_empty() {
+            Preset::Prompt(prompt)
+        } else {
+            Preset::WithParams { prompt, params }
+        }
+    }
+
+    pub fn prompt(&self) -> &str {
+        match self {
+            Preset::Prompt(prompt) | Preset::WithParams { prompt, .. } => prompt,
+        }
+    }
+
+    pub fn params(&self) -> GenerationParams {
+        match self {
+            Preset::Prompt(_) => GenerationParams::default(),
+            Preset::WithParams { params, .. } => params.clone(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Settings {
     /// The AI provider to use for questions
     #[serde(skip_serializing_if = "Option::is_none")]
     pub provider: Option<AIProvider>,
     /// The AI model to use for questions
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub model: Option<AIModel>,
-    /// The timeout for AI requests in seconds
+    pub model: Option<String>,
+    /// The timeout for AI requests that aren't streamed in seconds
     #[serde(skip_serializing_if = "Option::is_none")]
     pub timeout: Option<u64>,
+    /// How long an answer may take to start streaming in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_token_timeout: Option<u64>,
+    /// How long a streaming answer may pause in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_timeout: Option<u64>,
+    /// The timeout for connecting to the provider in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_timeout: Option<u64>,
+    /// Proxy URL all requests go through
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// Comma-separated hosts that are not sent through the proxy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_proxy: Option<String>,
+    /// PEM file with extra certificates to trust, e.g. of a TLS-intercepting proxy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_bundle: Option<path::PathBuf>,
+    /// Accept invalid TLS certificates, dangerous
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub insecure_skip_verify: Option<bool>,
+    /// How answers are shown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<OutputMode>,
+    /// Whether to print the answer to the normal screen after leaving the TUI
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_answer: Option<bool>,
+    /// Prices by model name, used to estimate the cost of requests
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prices: Option<HashMap<String, ModelPrice>>,
+    /// Default generation parameters, overridden by presets and flags
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub params: Option<GenerationParams>,
     /// Presets for common questions
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub presets: Option<HashMap<String, String>>,
+    pub presets: Option<HashMap<String, Preset>>,
+    /// Command lines the run_command tool may run, matched by their leading words
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_commands: Option<Vec<String>>,
+    /// Models `ask serve` answers with, in order, when the provider fails
+    /// before the answer starts, as provider:model
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<Vec<String>>,
+    /// Whether answers are cached and reused for identical questions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache: Option<bool>,
+    /// How long cached answers are reused, in hours
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_ttl: Option<u64>,
+    /// Size the cache is kept under, in megabytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_max_size: Option<u64>,
+    /// MCP servers by name, their tools are offered along with the built-in ones
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mcp_servers: Option<HashMap<String, McpServer>>,
+    /// Replaces the DeepSeek API URL, e.g. for a gateway
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deepseek_base_url: Option<String>,
+    /// Replaces the Grok API URL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grok_base_url: Option<String>,
+    /// Replaces the Qwen API URL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qwen_base_url: Option<String>,
     /// DeepSeek API key
     #[serde(skip_serializing)]
     pub deepseek_key: Option<String>,
@@ -31,14 +155,86 @@ pub struct Settings {
 }
 
 impl Settings {
-    fn get_config_path() -> String {
+    /// Directory holding the settings and the other files ask keeps
+    pub fn config_dir() -> path::PathBuf {
         let home_dir = dirs::home_dir().expect("Could not find home directory");
-        let config_path = home_dir.join(".config").join("ask.json");
-        std::fs::create_dir_all(config_path.parent().unwrap())
-            .expect("Failed to create config directory");
+        let config_dir = home_dir.join(".config");
+        std::fs::create_dir_all(&config_dir).expect("Failed to create config directory");
+        config_dir
+    }
+
+    fn get_config_path() -> String {
+        let config_path = Self::config_dir().join("ask.json");
         config_path.to_str().unwrap().to_string()
     }
 
+    pub fn api_key(&self, provider: AIProvider) -> Option<&str> {
+        match provider {
+            AIProvider::DeepSeek => self.deepseek_key.as_deref(),
+            AIProvider::Grok => self.grok_key.as_deref(),
+            AIProvider::Qwen => self.qwen_key.as_deref(),
+        }
+    }
+
+    /// How requests reach the providers
+    pub fn http_options(&self) -> HttpOptions {
+        HttpOptions {
+            timeout: self.timeout,
+            connect_timeout: self.connect_timeout,
+            first_token_timeout: self.first_token_timeout,
+            idle_timeout: self.idle_timeout,
+            proxy: self.proxy.clone(),
+            no_proxy: self.no_proxy.clone(),
+            ca_bundle: self.ca_bundle.clone(),
+            insecure_skip_verify: self.insecure_skip_verify.unwrap_or(false),
+            base_url: None,
+        }
+    }
+
+    /// How requests reach the API of the provider, at its base URL if it is
+    /// replaced
+    pub fn http_options_for(&self, provider: AIProvider) -> HttpOptions {
+        let base_url = match provider {
+            AIProvider::DeepSeek => &self.deepseek_base_url,
+            AIProvider::Grok => &self.grok_base_url,
+            AIProvider::Qwen => &self.qwen_base_url,
+        };
+        HttpOptions {
+            base_url: base_url.clone(),
+            ..self.http_options()
+        }
+    }
+
+    /// The settings with another provider and/or model
+    pub fn with_model(&self, provider: Option<AIProvider>, model: Option<&str>) -> Settings {
+        let mut settings = self.clone();
+
+        if let Some(provider) = provider
+            && settings.provider != Some(provider)
+        {
+            settings.provider = Some(provider);
+            // The configured model most likely belongs to the previous provider
+            let known = crate::models::cached(provider);
+            if model.is_none()
+                && !settings
+                    .model
+                    .as_ref()
+                    .is_some_and(|model| known.contains(model))
+            {
+                settings.model = provider.builtin_models().first().map(|m| m.to_string());
+            }
+        }
+        if let Some(model) = model {
+            settings.model = Some(model.to_owned());
+        }
+
+        settings
+    }
+
+    pub fn price(&self, model: &str) -> Option<ModelPrice> {
+        self.prices.as_ref()?.get(model).copied()
+    }
+
     pub fn load() -> Result<Settings> {
         let config_path = Self::get_config_path();
 
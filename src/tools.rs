@@ -0,0 +1,736 @@
+use crate::ai::{
+    self, CancelHandle, EventStream, HttpOptions, Message, StreamEvent, ToolCall, ToolDefinition,
+    ToolRequest,
+};
+use crate::mcp::{self, McpClient};
+use crate::settings::{McpServer, Settings};
+use crate::util::unix_now;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{BufRead, IsTerminal, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+
+/// File in the config directory that every tool call is logged to, one JSON object per line
+static AUDIT_FILE: &str = "ask_tools.jsonl";
+/// Command lines run_command may run unless `tool_commands` is configured
+static DEFAULT_COMMANDS: &[&str] = &[
+    "ls",
+    "pwd",
+    "cat",
+    "head",
+    "tail",
+    "wc",
+    "git status",
+    "git log",
+    "git diff",
+    "git show",
+];
+/// Tool output beyond this many bytes is cut off, it all goes into the prompt
+static MAX_OUTPUT: usize = 32 * 1024;
+/// Lines grep reports at most
+static MAX_MATCHES: usize = 200;
+/// Requests the model may make before the answer is given up on
+static MAX_ROUNDS: usize = 10;
+static COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+static FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often the agent checks whether it was cancelled while a request streams
+static POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The built-in tools offered to the model
+fn builtin_definitions() -> Vec<ToolDefinition> {
+    vec![
+        ToolDefinition {
+            name: "read_file".to_owned(),
+            description: "Read a text file in the current working directory".to_owned(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string", "description": "Path relative to the working directory" }
+                },
+                "required": ["path"]
+            }),
+        },
+        ToolDefinition {
+            name: "list_dir".to_owned(),
+            description: "List the entries of a directory in the current working directory, directories end with /".to_owned(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string", "description": "Path relative to the working directory, defaults to ." }
+                }
+            }),
+        },
+        ToolDefinition {
+            name: "grep".to_owned(),
+            description: "Search the text files under a directory for lines containing a string, reported as path:line: text".to_owned(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "pattern": { "type": "string", "description": "The text to search for, not a regular expression" },
+                    "path": { "type": "string", "description": "File or directory to search, defaults to ." },
+                    "ignore_case": { "type": "boolean" }
+                },
+                "required": ["pattern"]
+            }),
+        },
+        ToolDefinition {
+            name: "run_command".to_owned(),
+            description: "Run a whitelisted command without a shell in the working directory and return its output".to_owned(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "command": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "The program and its arguments, e.g. [\"git\", \"log\", \"-5\"]"
+                    }
+                },
+                "required": ["command"]
+            }),
+        },
+        ToolDefinition {
+            name: "fetch_url".to_owned(),
+            description: "Fetch an http or https URL and return the body as text".to_owned(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "url": { "type": "string" }
+                },
+                "required": ["url"]
+            }),
+        },
+    ]
+}
+
+/// Runs the conversation with tools once the stream is first read, which has
+/// to be on the shared runtime, e.g. through [`ai::forward`]. Whenever the
+/// model asks for tool calls, each is sent as a [`StreamEvent::ToolRequest`]
+/// and only runs once the user allowed it, then the results are sent back to
+/// the model until it answers. The MCP servers in the settings are started
+/// first and their tools are offered as well. Dropping the stream stops the
+/// conversation.
+///
+/// `request` starts one request of the conversation, the events of every
+/// request are passed on except the tool calls themselves.
+pub fn start<F>(messages: Vec<Message>, settings: &Settings, request: F) -> EventStream
+where
+    F: Fn(&[Message], &[ToolDefinition]) -> Result<(Receiver<StreamEvent>, CancelHandle)>
+        + Send
+        + 'static,
+{
+    let commands = allowed_commands(settings);
+    let http = settings.http_options();
+    // Sorted, so the tools are always offered in the same order
+    let servers: BTreeMap<String, McpServer> = settings
+        .mcp_servers
+        .clone()
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+
+    Box::pin(async_stream::stream! {
+        let (tx, mut events) = unbounded_channel();
+        // Running the tools and waiting for the user to confirm them blocks
+        tokio::task::spawn_blocking(move || {
+            let agent = Agent { tx };
+            let result = Toolbox::new(commands, http).and_then(|mut toolbox| {
+                for (name, server) in &servers {
+                    let note = match toolbox.add_server(name, server) {
+                        Ok(skipped) if skipped.is_empty() => continue,
+                        Ok(skipped) => format!(
+                            "MCP server `{name}` has tools the model can't call, their names may only contain letters, digits, - and _ and be at most 64 characters with the server name: {}",
+                            skipped.join(", ")
+                        ),
+                        Err(err) => format!("MCP server `{name}` is not available: {err:#}"),
+                    };
+                    agent.note(&note);
+                }
+                agent.run(messages, &mut toolbox, request)
+            });
+            if let Err(err) = result {
+                agent.tx.send(StreamEvent::Error(err.into())).ok();
+            }
+        });
+        while let Some(event) = events.recv().await {
+            yield event;
+        }
+    })
+}
+
+struct Agent {
+    /// Closed once the stream is dropped
+    tx: UnboundedSender<StreamEvent>,
+}
+
+impl Agent {
+    fn run<F>(&self, mut messages: Vec<Message>, toolbox: &mut Toolbox, request: F) -> Result<()>
+    where
+        F: Fn(&[Message], &[ToolDefinition]) -> Result<(Receiver<StreamEvent>, CancelHandle)>,
+    {
+        for _ in 0..MAX_ROUNDS {
+            let (events, request_cancel) = request(&messages, &toolbox.definitions)?;
+            let mut answer = String::new();
+            let mut calls = Vec::new();
+
+            loop {
+                if self.tx.is_closed() {
+                    request_cancel.cancel();
+                    return Ok(());
+                }
+                let event = match events.recv_timeout(POLL_INTERVAL) {
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                    Ok(event) => event,
+                };
+                match event {
+                    StreamEvent::ToolCalls(requested) => calls = requested,
+                    StreamEvent::Error(err) => return Err(err.into()),
+                    event => {
+                        if let StreamEvent::Delta(text) = &event {
+                            answer.push_str(text);
+                        }
+                        // Nobody is reading the reply anymore
+                        if self.tx.send(event).is_err() {
+                            request_cancel.cancel();
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+
+            if calls.is_empty() {
+                return Ok(());
+            }
+
+            messages.push(Message::assistant_tool_calls(answer, calls.clone()));
+            for call in calls {
+                let Some(allowed) = self.confirm(&call) else {
+                    return Ok(());
+                };
+                let result = if allowed {
+                    toolbox.execute(&call)
+                } else {
+                    Err(anyhow::anyhow!("The user denied this tool call"))
+                };
+                record(&AuditRecord::new(&call, allowed, &result))
+                    .with_context(|| "Failed to write the tool audit log")?;
+
+                let status = match (&result, allowed) {
+                    (_, false) => "denied",
+                    (Err(_), true) => "failed",
+                    (_, true) => "done",
+                };
+                if !self.note(&format!("Tool call `{}` ({status})", describe(&call))) {
+                    return Ok(());
+                }
+
+                let content = result.unwrap_or_else(|err| format!("Error: {err:#}"));
+                messages.push(Message::tool(call.id, content));
+            }
+        }
+
+        Err(anyhow::anyhow!(
+            "Gave up after {MAX_ROUNDS} rounds of tool calls without an answer"
+        ))
+    }
+
+    /// Shows what the agent did as a quote in the answer, returns whether
+    /// anyone is still reading it
+    fn note(&self, text: &str) -> bool {
+        let note = format!("\n\n> {text}\n\n");
+        self.tx.send(StreamEvent::Delta(note)).is_ok()
+    }
+
+    /// Asks the view whether the call may run. None if the view is gone,
+    /// dropped the request without answering, or the answer was stopped.
+    fn confirm(&self, call: &ToolCall) -> Option<bool> {
+        let (reply, answer) = mpsc::channel();
+        let request = ToolRequest {
+            call: call.clone(),
+            reply,
+        };
+        self.tx.send(StreamEvent::ToolRequest(request)).ok()?;
+        let allowed = answer.recv().ok()?;
+        (!self.tx.is_closed()).then_some(allowed)
+    }
+}
+
+/// One line for the user, e.g. `read_file {"path":"src/main.rs"}`
+pub fn describe(call: &ToolCall) -> String {
+    format!("{} {}", call.function.name, call.function.arguments.trim())
+}
+
+/// Asks on the terminal whether a tool call may run, for the views without a
+/// TUI. Without a terminal to ask on, every call is denied.
+pub fn confirm_on_terminal(call: &ToolCall) -> bool {
+    let mut err = std::io::stderr();
+    if !std::io::stdin().is_terminal() {
+        writeln!(
+            err,
+            "Denied tool call {}, no terminal to confirm it on",
+            describe(call)
+        )
+        .ok();
+        return false;
+    }
+
+    write!(err, "Run tool call {}? [y/N] ", describe(call)).ok();
+    err.flush().ok();
+    let mut answer = String::new();
+    if std::io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// The tools the model may call: the built-in ones and those of the MCP servers
+struct Toolbox {
+    workspace: Workspace,
+    commands: Vec<String>,
+    /// Used to fetch URLs, through the proxy if there is one
+    http: HttpOptions,
+    servers: Vec<McpClient>,
+    definitions: Vec<ToolDefinition>,
+}
+
+impl Toolbox {
+    fn new(commands: Vec<String>, http: HttpOptions) -> Result<Self> {
+        Ok(Self {
+            workspace: Workspace::current()?,
+            commands,
+            http,
+            servers: Vec::new(),
+            definitions: builtin_definitions(),
+        })
+    }
+
+    /// Starts an MCP server and offers its tools, returns the names of the
+    /// tools it can't offer
+    fn add_server(&mut self, name: &str, server: &McpServer) -> Result<Vec<String>> {
+        // The server name starts the names of its tools
+        if !mcp::is_valid_name(name) || name.contains(mcp::SEPARATOR) {
+            return Err(anyhow::anyhow!(
+                "the name may only contain letters, digits, - and single _, and be at most 64 characters"
+            ));
+        }
+        let mut client = McpClient::start(name, server)?;
+        let (tools, skipped) = client.tools()?;
+        self.definitions.extend(tools);
+        self.servers.push(client);
+        Ok(skipped)
+    }
+
+    /// Runs a tool call, the output is sent to the model
+    fn execute(&mut self, call: &ToolCall) -> Result<String> {
+        let args: Value = if call.function.arguments.trim().is_empty() {
+            json!({})
+        } else {
+            serde_json::from_str(&call.function.arguments)
+                .with_context(|| format!("Invalid arguments: {}", call.function.arguments))?
+        };
+
+        // MCP tools are named `{server}__{tool}`
+        if let Some((server, tool)) = call.function.name.split_once(mcp::SEPARATOR)
+            && let Some(client) = self.servers.iter_mut().find(|client| client.name == server)
+        {
+            return client.call(tool, args).map(truncate);
+        }
+
+        execute_builtin(
+            &call.function.name,
+            &args,
+            &self.workspace,
+            &self.commands,
+            &self.http,
+        )
+    }
+}
+
+fn execute_builtin(
+    name: &str,
+    args: &Value,
+    workspace: &Workspace,
+    commands: &[String],
+    http: &HttpOptions,
+) -> Result<String> {
+    let string_arg = |name: &str| args[name].as_str();
+    let required =
+        |name: &str| string_arg(name).ok_or_else(|| anyhow::anyhow!("Missing argument: {name}"));
+
+    let output = match name {
+        "read_file" => read_file(workspace, required("path")?)?,
+        "list_dir" => list_dir(workspace, string_arg("path").unwrap_or("."))?,
+        "grep" => grep(
+            workspace,
+            required("pattern")?,
+            string_arg("path").unwrap_or("."),
+            args["ignore_case"].as_bool().unwrap_or(false),
+        )?,
+        "run_command" => {
+            let command = args["command"]
+                .as_array()
+                .ok_or_else(|| anyhow::anyhow!("Missing argument: command"))?
+                .iter()
+                .map(|arg| arg.as_str().map(str::to_owned))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| anyhow::anyhow!("The command must be a list of strings"))?;
+            run_command(workspace, &command, commands)?
+        }
+        "fetch_url" => fetch_url(required("url")?, http)?,
+        name => return Err(anyhow::anyhow!("Unknown tool: {name}")),
+    };
+
+    Ok(truncate(output))
+}
+
+/// The command lines run_command may run
+pub fn allowed_commands(settings: &Settings) -> Vec<String> {
+    match &settings.tool_commands {
+        Some(commands) => commands.clone(),
+        None => DEFAULT_COMMANDS.iter().map(|c| c.to_string()).collect(),
+    }
+}
+
+fn truncate(mut output: String) -> String {
+    if output.len() > MAX_OUTPUT {
+        let mut end = MAX_OUTPUT;
+        while !output.is_char_boundary(end) {
+            end -= 1;
+        }
+        output.truncate(end);
+        output.push_str("\n[output truncated]");
+    }
+    output
+}
+
+/// The directory the tools are confined to, the working directory ask was started in
+struct Workspace {
+    root: PathBuf,
+}
+
+impl Workspace {
+    fn current() -> Result<Self> {
+        let root = std::env::current_dir()?.canonicalize()?;
+        Ok(Self { root })
+    }
+
+    /// Resolves a path the model gave, which must exist and, with symlinks
+    /// followed, lie inside the workspace
+    fn resolve(&self, path: &str) -> Result<PathBuf> {
+        let resolved = self
+            .root
+            .join(path)
+            .canonicalize()
+            .with_context(|| format!("Cannot access {path}"))?;
+        if !resolved.starts_with(&self.root) {
+            return Err(anyhow::anyhow!(
+                "{path} is outside of the working directory"
+            ));
+        }
+        Ok(resolved)
+    }
+
+    fn relative<'a>(&self, path: &'a Path) -> &'a Path {
+        path.strip_prefix(&self.root).unwrap_or(path)
+    }
+}
+
+fn read_file(workspace: &Workspace, path: &str) -> Result<String> {
+    let path = workspace.resolve(path)?;
+    let content = std::fs::read(&path)?;
+    String::from_utf8(content).map_err(|_| anyhow::anyhow!("Not a text file"))
+}
+
+fn list_dir(workspace: &Workspace, path: &str) -> Result<String> {
+    let path = workspace.resolve(path)?;
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(&path)? {
+        let entry = entry?;
+        let mut name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_dir() {
+            name.push('/');
+        }
+        entries.push(name);
+    }
+    entries.sort();
+    Ok(entries.join("\n"))
+}
+
+fn grep(workspace: &Workspace, pattern: &str, path: &str, ignore_case: bool) -> Result<String> {
+    let pattern = if ignore_case {
+        pattern.to_lowercase()
+    } else {
+        pattern.to_owned()
+    };
+    let mut matches = Vec::new();
+    let mut pending = vec![workspace.resolve(path)?];
+
+    while let Some(path) = pending.pop() {
+        if path.is_dir() {
+            for entry in std::fs::read_dir(&path)? {
+                let entry = entry?;
+                // Hidden entries are mostly VCS data and caches
+                if entry.file_name().to_string_lossy().starts_with('.') {
+                    continue;
+                }
+                // Symlinks could lead out of the workspace
+                if !entry.file_type()?.is_symlink() {
+                    pending.push(entry.path());
+                }
+            }
+            continue;
+        }
+
+        // Binary files are skipped
+        let Some(content) = std::fs::read(&path)
+            .ok()
+            .and_then(|content| String::from_utf8(content).ok())
+        else {
+            continue;
+        };
+        for (number, line) in content.lines().enumerate() {
+            let found = if ignore_case {
+                line.to_lowercase().contains(&pattern)
+            } else {
+                line.contains(&pattern)
+            };
+            if found {
+                matches.push(format!(
+                    "{}:{}: {line}",
+                    workspace.relative(&path).display(),
+                    number + 1
+                ));
+                if matches.len() == MAX_MATCHES {
+                    matches.push(format!("[stopped after {MAX_MATCHES} matches]"));
+                    return Ok(matches.join("\n"));
+                }
+            }
+        }
+    }
+
+    if matches.is_empty() {
+        return Ok("No matches".to_owned());
+    }
+    Ok(matches.join("\n"))
+}
+
+/// Arguments may only name paths inside the workspace, with symlinks
+/// followed. Git's diff options could also write files with --output.
+fn check_argument(workspace: &Workspace, arg: &str) -> Result<()> {
+    let not_allowed = || anyhow::anyhow!("The argument {arg} is not allowed");
+    if arg.starts_with("--output") {
+        return Err(not_allowed());
+    }
+    // The value of an option such as --git-dir=path
+    let path = match arg.split_once('=') {
+        Some((option, value)) if option.starts_with('-') => value,
+        _ if arg.starts_with('-') => return Ok(()),
+        _ => arg,
+    };
+
+    let path = Path::new(path);
+    let escapes = path.has_root()
+        || path.starts_with("~")
+        || path.components().any(|component| {
+            matches!(
+                component,
+                Component::Prefix(_) | Component::RootDir | Component::ParentDir
+            )
+        });
+    if escapes {
+        return Err(not_allowed());
+    }
+    if workspace.root.join(path).symlink_metadata().is_ok() {
+        workspace.resolve(&path.to_string_lossy())?;
+    }
+    Ok(())
+}
+
+fn run_command(workspace: &Workspace, command: &[String], allowed: &[String]) -> Result<String> {
+    let Some((program, args)) = command.split_first() else {
+        return Err(anyhow::anyhow!("The command is empty"));
+    };
+
+    let is_allowed = allowed.iter().any(|line| {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        !words.is_empty()
+            && words.len() <= command.len()
+            && words.iter().zip(command).all(|(word, arg)| word == arg)
+    });
+    if !is_allowed {
+        return Err(anyhow::anyhow!(
+            "{program} is not an allowed command, allowed are: {}",
+            allowed.join(", ")
+        ));
+    }
+    for arg in args {
+        check_argument(workspace, arg)?;
+    }
+
+    let mut child = Command::new(program)
+        .args(args)
+        .current_dir(&workspace.root)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run {program}"))?;
+
+    // Read the output in threads, a full pipe would block the command
+    let mut stdout = child.stdout.take().unwrap();
+    let mut stderr = child.stderr.take().unwrap();
+    let stdout = std::thread::spawn(move || {
+        let mut output = Vec::new();
+        stdout.read_to_end(&mut output).ok();
+        output
+    });
+    let stderr = std::thread::spawn(move || {
+        let mut output = Vec::new();
+        stderr.read_to_end(&mut output).ok();
+        output
+    });
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if started.elapsed() > COMMAND_TIMEOUT {
+            child.kill().ok();
+            child.wait().ok();
+            return Err(anyhow::anyhow!(
+                "{program} did not finish within {} seconds",
+                COMMAND_TIMEOUT.as_secs()
+            ));
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    };
+
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    let mut output = String::from_utf8_lossy(&stdout).into_owned();
+    if !stderr.is_empty() {
+        output.push_str("\n[stderr]\n");
+        output.push_str(&String::from_utf8_lossy(&stderr));
+    }
+    if !status.success() {
+        output.push_str(&format!("\n[{status}]"));
+    }
+    Ok(output)
+}
+
+fn fetch_url(url: &str, http: &HttpOptions) -> Result<String> {
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return Err(anyhow::anyhow!("Only http and https URLs can be fetched"));
+    }
+
+    let client = http.client()?;
+    ai::block_on(async {
+        let resp = client.get(url).timeout(FETCH_TIMEOUT).send().await?;
+        if !resp.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "Request failed with status: {}",
+                resp.status()
+            ));
+        }
+        Ok(resp.text().await?)
+    })
+}
+
+/// One tool call in the audit log
+#[derive(Debug, Serialize)]
+struct AuditRecord {
+    /// Unix time in seconds
+    time: u64,
+    /// Working directory the call ran in
+    cwd: String,
+    tool: String,
+    arguments: String,
+    allowed: bool,
+    /// Size of the output sent to the model
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output_bytes: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl AuditRecord {
+    fn new(call: &ToolCall, allowed: bool, result: &Result<String>) -> Self {
+        let (output_bytes, error) = match result {
+            Err(_) if !allowed => (None, None),
+            Err(err) => (None, Some(format!("{err:#}"))),
+            Ok(output) => (Some(output.len()), None),
+        };
+        Self {
+            time: unix_now(),
+            cwd: std::env::current_dir()
+                .map(|dir| dir.display().to_string())
+                .unwrap_or_default(),
+            tool: call.function.name.clone(),
+            arguments: call.function.arguments.clone(),
+            allowed,
+            output_bytes,
+            error,
+        }
+    }
+}
+
+/// Appends a tool call to the audit log
+fn record(record: &AuditRecord) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(Settings::config_dir().join(AUDIT_FILE))?;
+    writeln!(file, "{}", serde_json::to_string(record)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arguments_stay_in_the_workspace() {
+        let root = std::env::temp_dir().join(format!("ask-tools-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("/", root.join("escape")).unwrap();
+        let workspace = Workspace {
+            root: root.canonicalize().unwrap(),
+        };
+
+        for arg in [
+            "src",
+            "-n",
+            "--stat",
+            "HEAD~3..HEAD",
+            "missing.rs",
+            "--git-dir=src",
+        ] {
+            assert!(check_argument(&workspace, arg).is_ok(), "{arg}");
+        }
+        for arg in [
+            "/etc/passwd",
+            "../x",
+            "src/../../x",
+            "~/.ssh",
+            "--git-dir=/",
+            "--output=x",
+        ] {
+            assert!(check_argument(&workspace, arg).is_err(), "{arg}");
+        }
+        #[cfg(unix)]
+        for arg in ["escape", "escape/etc/passwd", "--git-dir=escape"] {
+            assert!(check_argument(&workspace, arg).is_err(), "{arg}");
+        }
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,934 @@
+use anyhow::Result;
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use ratatui::DefaultTerminal;
+use ratatui::Frame;
+use ratatui::buffer::Buffer;
+use ratatui::crossterm::event::{
+    self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind,
+    KeyModifiers, MouseEvent, MouseEventKind,
+};
+use ratatui::crossterm::style::{Attribute, SetAttribute, SetBackgroundColor, SetForegroundColor};
+use ratatui::crossterm::{execute, queue, terminal};
+use ratatui::layout::{Alignment, Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::text::{Line, Span, Text};
+use ratatui::widgets::{Paragraph, Scrollbar, ScrollbarOrientation, ScrollbarState, Widget, Wrap};
+use ratatui::{TerminalOptions, Viewport};
+use std::io::{Write, stdout};
+use std::ops::Range;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+
+use crate::ai::{CancelHandle, StreamEvent, ToolRequest, Usage};
+use crate::markdown::{self, CodeBlock};
+use crate::settings::ModelPrice;
+use crate::tools;
+use ask::AskError;
+
+/// How often the screen is redrawn when nothing else happens
+pub static TICK_RATE: Duration = Duration::from_millis(50);
+/// How long a notice replaces the key hints in the status line
+pub static NOTICE_DURATION: Duration = Duration::from_secs(3);
+/// Smallest height of the inline answer view
+static INLINE_MIN_HEIGHT: u16 = 10;
+/// Lines moved by one mouse wheel step
+static WHEEL_STEP: i32 = 3;
+static SPINNER_FRAMES: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+
+#[derive(Debug, PartialEq)]
+pub enum Status {
+    /// Waiting for the first token
+    Waiting,
+    /// Receiving the answer
+    Streaming,
+    /// The answer is complete
+    Done,
+    /// The request failed
+    Failed(String),
+    /// The user stopped the request, the partial answer is kept
+    Stopped,
+}
+
+/// Search within the rendered question and answer
+#[derive(Debug, Default)]
+struct Search {
+    /// Query being typed after `/`, None when not typing
+    input: Option<String>,
+    /// Confirmed query, empty when not searching
+    query: String,
+    /// Index of the current match
+    current: usize,
+    /// Number of matches in the last frame
+    count: usize,
+    /// Scroll to a match in the next frame, which knows the wrapped layout
+    jump: Option<Jump>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Jump {
+    /// The first match at or below the top of the view
+    FromView,
+    /// The current match
+    Current,
+}
+
+/// A search match, as a byte range within a rendered line
+#[derive(Debug, Clone, PartialEq)]
+struct Match {
+    line: usize,
+    range: Range<usize>,
+}
+
+/// Everything the rendered text depends on, besides the question
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct RenderKey {
+    answer_len: usize,
+    reasoning_len: usize,
+    reasoning_expanded: bool,
+    focused_block: Option<usize>,
+    in_flight: bool,
+}
+
+struct App<'a> {
+    question: &'a str,
+    answer: String,
+    /// Reasoning streamed by reasoning models before the answer
+    reasoning: String,
+    /// Set once the user toggles the reasoning section, which otherwise is
+    /// expanded only until the answer starts
+    reasoning_expanded: Option<bool>,
+    /// Token counts, once the provider reports them
+    usage: Option<Usage>,
+    /// Used to estimate the cost from the usage
+    price: Option<ModelPrice>,
+    status: Status,
+    started: Instant,
+    /// Time the request took, set once it is no longer in flight
+    elapsed: Option<Duration>,
+    scroll: Scroll,
+    /// Code block selected for copying, index into the blocks of the answer
+    focused_block: Option<usize>,
+    /// Short-lived message shown in the status line
+    notice: Option<(String, Instant)>,
+    /// Cache of `rendered_text`, keyed by what the rendering depends on
+    rendered: Option<(RenderKey, Text<'static>)>,
+    search: Search,
+    /// Tool call waiting for the user to allow or deny it
+    tool_request: Option<ToolRequest>,
+    /// Set once the user allowed every tool call of this answer
+    allow_tools: bool,
+    /// Why the request failed, returned once the view is closed
+    failure: Option<AskError>,
+    quit: bool,
+}
+
+impl<'a> App<'a> {
+    fn new(question: &'a str, price: Option<ModelPrice>) -> Self {
+        Self {
+            question,
+            answer: String::new(),
+            reasoning: String::new(),
+            reasoning_expanded: None,
+            usage: None,
+            price,
+            status: Status::Waiting,
+            started: Instant::now(),
+            elapsed: None,
+            scroll: Scroll::default(),
+            focused_block: None,
+            notice: None,
+            rendered: None,
+            search: Search::default(),
+            tool_request: None,
+            allow_tools: false,
+            failure: None,
+            quit: false,
+        }
+    }
+
+    fn is_reasoning_expanded(&self) -> bool {
+        self.reasoning_expanded.unwrap_or(self.answer.is_empty())
+    }
+
+    fn in_flight(&self) -> bool {
+        matches!(self.status, Status::Waiting | Status::Streaming)
+    }
+
+    fn finish(&mut self, status: Status) {
+        self.status = status;
+        self.elapsed = Some(self.started.elapsed());
+    }
+
+    fn on_stream_event(&mut self, event: StreamEvent) {
+        match event {
+            // With tools every request of the conversation reports its own
+            StreamEvent::Usage(usage) => *self.usage.get_or_insert_default() += usage,
+            StreamEvent::Reasoning(content) => {
+                self.status = Status::Streaming;
+                self.reasoning.push_str(&content);
+            }
+            StreamEvent::Delta(content) => {
+                self.status = Status::Streaming;
+                self.answer.push_str(&content);
+            }
+            StreamEvent::ToolRequest(request) if self.allow_tools => {
+                request.reply.send(true).ok();
+            }
+            StreamEvent::ToolRequest(request) => self.tool_request = Some(request),
+            // Only sent to the tools agent, which answers them with requests
+            StreamEvent::ToolCalls(_) => {}
+            StreamEvent::Error(err) => {
+                self.finish(Status::Failed(err.to_string()));
+                self.failure = Some(err);
+            }
+        }
+    }
+
+    /// Keys while a tool call waits for confirmation, returns whether the key was used
+    fn on_tool_key(&mut self, key: KeyEvent) -> bool {
+        let allowed = match key.code {
+            KeyCode::Char('y') => true,
+            KeyCode::Char('a') => {
+                self.allow_tools = true;
+                true
+            }
+            KeyCode::Char('n') => false,
+            _ => return false,
+        };
+        if let Some(request) = self.tool_request.take() {
+            request.reply.send(allowed).ok();
+        }
+        true
+    }
+
+    fn on_key(&mut self, key: KeyEvent) {
+        let ctrl_c =
+            key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c');
+
+        if ctrl_c {
+            // Like an interrupted command, unlike leaving with q
+            if self.in_flight() {
+                self.failure = Some(AskError::Cancelled);
+            }
+            self.quit = true;
+            return;
+        }
+        if self.tool_request.is_some() && self.on_tool_key(key) {
+            return;
+        }
+        if self.search.input.is_some() {
+            self.on_search_key(key);
+            return;
+        }
+
+        match key.code {
+            KeyCode::Char('q') => self.quit = true,
+            KeyCode::Esc if !self.search.query.is_empty() => self.search = Search::default(),
+            KeyCode::Esc | KeyCode::Char('s') => self.stop(),
+            KeyCode::Char('/') => self.search.input = Some(String::new()),
+            KeyCode::Char('t') => self.reasoning_expanded = Some(!self.is_reasoning_expanded()),
+            KeyCode::Char('n') => self.next_match(1),
+            KeyCode::Char('N') => self.next_match(-1),
+            KeyCode::Char('y') => self.copy_answer(),
+            KeyCode::Tab => self.focus_block(1),
+            KeyCode::BackTab => self.focus_block(-1),
+            KeyCode::Char('c') | KeyCode::Enter => self.copy_focused_block(),
+            code => self.scroll.on_key(code),
+        }
+    }
+
+    /// Keys while typing a search query
+    fn on_search_key(&mut self, key: KeyEvent) {
+        let Some(input) = &mut self.search.input else {
+            return;
+        };
+
+        match key.code {
+            KeyCode::Char(c) => input.push(c),
+            KeyCode::Backspace if input.is_empty() => self.search.input = None,
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Enter => {
+                let query = self.search.input.take().unwrap_or_default();
+                self.search = Search {
+                    jump: (!query.is_empty()).then_some(Jump::FromView),
+                    query,
+                    ..Search::default()
+                };
+            }
+            KeyCode::Esc => self.search.input = None,
+            _ => {}
+        }
+    }
+
+    fn next_match(&mut self, step: isize) {
+        if self.search.query.is_empty() {
+            self.notify("Press / to search");
+            return;
+        }
+        if self.search.count == 0 {
+            return;
+        }
+        let count = self.search.count as isize;
+        self.search.current = (self.search.current as isize + step).rem_euclid(count) as usize;
+        self.search.jump = Some(Jump::Current);
+    }
+
+    fn notify(&mut self, message: impl Into<String>) {
+        self.notice = Some((message.into(), Instant::now()));
+    }
+
+    fn copy(&mut self, text: &str, what: &str) {
+        match copy_to_clipboard(text) {
+            Ok(()) => self.notify(format!("Copied {what}")),
+            Err(err) => self.notify(format!("Failed to copy {what}: {err}")),
+        }
+    }
+
+    fn copy_answer(&mut self) {
+        if self.answer.is_empty() {
+            self.notify("Nothing to copy yet");
+            return;
+        }
+        let answer = self.answer.clone();
+        self.copy(&answer, "answer as markdown");
+    }
+
+    /// Moves the code block focus forwards or backwards, wrapping around
+    fn focus_block(&mut self, step: isize) {
+        let blocks = markdown::code_blocks(&self.answer);
+        if blocks.is_empty() {
+            self.notify("No code blocks in the answer");
+            return;
+        }
+
+        let index = match self.focused_block {
+            Some(index) => (index as isize + step).rem_euclid(blocks.len() as isize) as usize,
+            None if step < 0 => blocks.len() - 1,
+            None => 0,
+        };
+        self.focused_block = Some(index);
+        self.notify(format!(
+            "Code block {}, press c to copy",
+            describe_block(&blocks[index], index, blocks.len())
+        ));
+    }
+
+    fn copy_focused_block(&mut self) {
+        if self.focused_block.is_none() {
+            self.focus_block(1);
+        }
+        let blocks = markdown::code_blocks(&self.answer);
+        let Some(index) = self.focused_block.filter(|&index| index < blocks.len()) else {
+            return;
+        };
+        let what = format!(
+            "code block {}",
+            describe_block(&blocks[index], index, blocks.len())
+        );
+        self.copy(&blocks[index].code, &what);
+    }
+
+    /// Stops generating but keeps the partial answer on screen
+    fn stop(&mut self) {
+        // Dropping a pending tool call ends the conversation
+        self.tool_request = None;
+        if self.in_flight() {
+            self.finish(Status::Stopped);
+        }
+    }
+
+    fn status_line(&self) -> Line<'static> {
+        let elapsed = self.elapsed.unwrap_or_else(|| self.started.elapsed());
+        let elapsed = format!("{:.1}s", elapsed.as_secs_f64());
+        let frame = spinner_frame(self.started);
+
+        let status = match &self.status {
+            _ if self.tool_request.is_some() => Span::from("? Allow tool call").yellow(),
+            Status::Waiting => {
+                Span::from(format!("{frame} Waiting for response {elapsed}")).yellow()
+            }
+            Status::Streaming if self.answer.is_empty() => {
+                Span::from(format!("{frame} Thinking {elapsed}")).cyan()
+            }
+            Status::Streaming => Span::from(format!("{frame} Generating {elapsed}")).cyan(),
+            Status::Done => Span::from(format!("✔ Done in {elapsed}")).green(),
+            Status::Failed(err) => Span::from(format!("✘ Failed after {elapsed}: {err}")).red(),
+            Status::Stopped => Span::from(format!("■ Stopped after {elapsed}")).red(),
+        };
+
+        let search = &self.search;
+        let hint = match &self.notice {
+            _ if let Some(request) = &self.tool_request => Span::from(format!(
+                "  {} [y yes, n no, a allow all]",
+                tools::describe(&request.call)
+            ))
+            .yellow(),
+            _ if search.input.is_some() => Span::from(format!(
+                "  /{}█",
+                search.input.as_deref().unwrap_or_default()
+            ))
+            .yellow(),
+            Some((notice, at)) if at.elapsed() < NOTICE_DURATION => {
+                Span::from(format!("  {notice}")).magenta()
+            }
+            _ if !search.query.is_empty() && search.count == 0 => {
+                Span::from(format!("  /{} no matches, Esc clear", search.query)).yellow()
+            }
+            _ if !search.query.is_empty() => Span::from(format!(
+                "  /{} {}/{}, n/N next/prev, Esc clear",
+                search.query,
+                search.current + 1,
+                search.count
+            ))
+            .yellow(),
+            _ => {
+                Span::from("  [q quit, s stop, y copy, Tab/c code blocks, j/k scroll]").dark_gray()
+            }
+        };
+
+        let mut spans = vec![status.bold()];
+        if let Some(usage) = self.usage {
+            let mut tokens = format!(
+                ", {} tokens ({} in, {} out)",
+                usage.total_tokens, usage.prompt_tokens, usage.completion_tokens
+            );
+            if let Some(price) = self.price {
+                let cost = price.cost(usage.prompt_tokens, usage.completion_tokens);
+                tokens.push_str(&format!(" ≈ {cost:.6}"));
+            }
+            spans.push(Span::from(tokens).green());
+        }
+        spans.push(hint);
+
+        Line::from(spans)
+    }
+
+    /// Rendered question and answer, rebuilt only when the answer changes
+    fn rendered_text(&mut self) -> Text<'static> {
+        let key = RenderKey {
+            answer_len: self.answer.len(),
+            reasoning_len: self.reasoning.len(),
+            reasoning_expanded: self.is_reasoning_expanded(),
+            focused_block: self.focused_block,
+            in_flight: self.in_flight(),
+        };
+        if let Some((cached_key, text)) = &self.rendered
+            && *cached_key == key
+        {
+            return text.clone();
+        }
+
+        let question = format!("# [Question]\n\n{}\n\n---", self.question);
+        let mut text = markdown::render(&question, None);
+
+        if !self.reasoning.is_empty() {
+            text.lines.push(Line::default());
+            if key.reasoning_expanded {
+                text.lines
+                    .push(Line::from("▼ Reasoning [t to collapse]").dark_gray().bold());
+                text.lines.push(Line::default());
+                for mut line in markdown::render(&self.reasoning, None).lines {
+                    for span in &mut line.spans {
+                        span.style = span.style.dark_gray().italic();
+                    }
+                    text.lines.push(line);
+                }
+            } else {
+                let lines = self.reasoning.lines().count();
+                let plural = if lines == 1 { "" } else { "s" };
+                text.lines.push(
+                    Line::from(format!("▶ Reasoning, {lines} line{plural} [t to expand]"))
+                        .dark_gray()
+                        .bold(),
+                );
+            }
+        }
+
+        let answer = if self.answer.is_empty() && self.in_flight() {
+            "Loading..."
+        } else {
+            &self.answer
+        };
+        text.lines.push(Line::default());
+        text.lines
+            .extend(markdown::render("# [Response]", None).lines);
+        text.lines.push(Line::default());
+        text.lines
+            .extend(markdown::render(answer, self.focused_block).lines);
+
+        self.rendered = Some((key, text.clone()));
+        text
+    }
+
+    fn render(&mut self, f: &mut Frame) {
+        let chunks = Layout::vertical([Constraint::Length(1), Constraint::Fill(1)])
+            .spacing(1)
+            .margin(1)
+            .split(f.area());
+
+        let status_paragraph = Paragraph::new(self.status_line()).alignment(Alignment::Left);
+
+        let mut text = self.rendered_text();
+
+        let [text_area, scrollbar_area] =
+            Layout::horizontal([Constraint::Fill(1), Constraint::Length(1)]).areas(chunks[1]);
+
+        let line_count = wrapped_height(text.clone(), text_area.width);
+        self.scroll.fit(line_count, text_area.height);
+
+        if !self.search.query.is_empty() {
+            let matches = find_matches(&text, &self.search.query);
+            self.search.count = matches.len();
+            self.search.current = self.search.current.min(matches.len().saturating_sub(1));
+            if let Some(jump) = self.search.jump.take()
+                && !matches.is_empty()
+            {
+                self.jump_to_match(&text, &matches, jump, text_area.width);
+            }
+            highlight_matches(&mut text, &matches, self.search.current);
+        }
+
+        let mut scrollbar_state = self.scroll.scrollbar_state();
+        let scrollbar = Scrollbar::new(ScrollbarOrientation::VerticalRight)
+            .begin_symbol(None)
+            .end_symbol(None);
+
+        f.render_widget(status_paragraph, chunks[0]);
+        f.render_widget(self.scroll.paragraph(text), text_area);
+        f.render_stateful_widget(scrollbar, scrollbar_area, &mut scrollbar_state);
+    }
+}
+
+impl App<'_> {
+    /// Scrolls so the match is in view, a third down from the top
+    fn jump_to_match(&mut self, text: &Text, matches: &[Match], jump: Jump, width: u16) {
+        let rows = || matches.iter().map(|m| display_row(text, m, width));
+
+        if jump == Jump::FromView {
+            let scroll = usize::from(self.scroll.offset);
+            self.search.current = rows().position(|row| row >= scroll).unwrap_or(0);
+        }
+
+        let Some(row) = rows().nth(self.search.current) else {
+            return;
+        };
+        let top = usize::from(self.scroll.offset);
+        let height = self.scroll.height;
+        if row < top || row >= top + usize::from(height) {
+            let row = row.saturating_sub(usize::from(height / 3));
+            self.scroll.to(u16::try_from(row).unwrap_or(u16::MAX));
+        }
+    }
+}
+
+/// Where a view of streamed text is scrolled to
+#[derive(Debug)]
+pub struct Scroll {
+    /// First row shown
+    pub offset: u16,
+    /// Keep the view pinned to the bottom while the text streams in
+    follow: bool,
+    /// Scroll limit of the last frame, from the wrapped height of the text
+    max: u16,
+    /// Height of the view in the last frame
+    pub height: u16,
+}
+
+impl Default for Scroll {
+    fn default() -> Self {
+        Self {
+            offset: 0,
+            follow: true,
+            max: 0,
+            height: 0,
+        }
+    }
+}
+
+impl Scroll {
+    /// Fits the scroll to the wrapped height of the text in the view
+    pub fn fit(&mut self, line_count: usize, height: u16) {
+        self.height = height;
+        self.max = u16::try_from(line_count)
+            .unwrap_or(u16::MAX)
+            .saturating_sub(height);
+        self.offset = if self.follow {
+            self.max
+        } else {
+            self.offset.min(self.max)
+        };
+    }
+
+    pub fn on_key(&mut self, code: KeyCode) {
+        // A page keeps one line of the previous page for context
+        let page = i32::from(self.height.saturating_sub(1).max(1));
+        match code {
+            KeyCode::Up | KeyCode::Char('k') => self.by(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.by(1),
+            KeyCode::PageUp => self.by(-page),
+            KeyCode::PageDown | KeyCode::Char(' ') => self.by(page),
+            KeyCode::Home | KeyCode::Char('g') => self.to(0),
+            KeyCode::End | KeyCode::Char('G') => self.to(self.max),
+            _ => {}
+        }
+    }
+
+    pub fn on_mouse(&mut self, mouse: MouseEvent) {
+        match mouse.kind {
+            MouseEventKind::ScrollUp => self.by(-WHEEL_STEP),
+            MouseEventKind::ScrollDown => self.by(WHEEL_STEP),
+            _ => {}
+        }
+    }
+
+    fn by(&mut self, delta: i32) {
+        let offset = (i32::from(self.offset) + delta).clamp(0, i32::from(self.max));
+        self.to(offset as u16);
+    }
+
+    /// Scrolling back to the bottom resumes following the stream
+    pub fn to(&mut self, offset: u16) {
+        self.offset = offset.min(self.max);
+        self.follow = self.offset >= self.max;
+    }
+
+    pub fn scrollbar_state(&self) -> ScrollbarState {
+        ScrollbarState::new(usize::from(self.max)).position(usize::from(self.offset))
+    }
+
+    /// The text, wrapped and scrolled to the offset
+    pub fn paragraph<'a>(&self, text: impl Into<Text<'a>>) -> Paragraph<'a> {
+        // Code blocks are rendered verbatim, so indentation must be kept
+        Paragraph::new(text)
+            .wrap(Wrap { trim: false })
+            .scroll((self.offset, 0))
+    }
+}
+
+/// Waits for input until the next tick, so the caller can redraw in a loop
+pub fn next_event() -> Result<Option<Event>> {
+    if event::poll(TICK_RATE)? {
+        Ok(Some(event::read()?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Spinner animation frame for a task that started at `started`
+pub fn spinner_frame(started: Instant) -> &'static str {
+    let tick = started.elapsed().as_millis() / TICK_RATE.as_millis();
+    SPINNER_FRAMES[tick as usize % SPINNER_FRAMES.len()]
+}
+
+/// Number of screen rows the text takes when wrapped to `width`
+pub fn wrapped_height<'a>(text: impl Into<Text<'a>>, width: u16) -> usize {
+    Paragraph::new(text)
+        .wrap(Wrap { trim: false })
+        .line_count(width)
+}
+
+/// Finds the query in the rendered lines. Like in most pagers the search
+/// only cares about case when the query contains a capital letter.
+fn find_matches(text: &Text, query: &str) -> Vec<Match> {
+    let case_sensitive = query.chars().any(char::is_uppercase);
+    let query = if case_sensitive {
+        query.to_owned()
+    } else {
+        query.to_ascii_lowercase()
+    };
+
+    let mut matches = Vec::new();
+    for (index, line) in text.lines.iter().enumerate() {
+        let mut content: String = line
+            .spans
+            .iter()
+            .map(|span| span.content.as_ref())
+            .collect();
+        if !case_sensitive {
+            content.make_ascii_lowercase();
+        }
+        matches.extend(content.match_indices(&query).map(|(start, _)| Match {
+            line: index,
+            range: start..start + query.len(),
+        }));
+    }
+
+    matches
+}
+
+/// Maps a match to the wrapped row it is displayed on
+fn display_row(text: &Text, m: &Match, width: u16) -> usize {
+    let rows_before: usize = text.lines[..m.line]
+        .iter()
+        .map(|line| wrapped_height(line.clone(), width))
+        .sum();
+
+    let content: String = text.lines[m.line]
+        .spans
+        .iter()
+        .map(|span| span.content.as_ref())
+        .collect();
+    let rows_within = wrapped_height(&content[..m.range.start], width) - 1;
+
+    rows_before + rows_within
+}
+
+fn highlight_matches(text: &mut Text<'static>, matches: &[Match], current: usize) {
+    for (index, line) in text.lines.iter_mut().enumerate() {
+        let ranges: Vec<(Range<usize>, Style)> = matches
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| m.line == index)
+            .map(|(i, m)| {
+                let style = if i == current {
+                    Style::new().black().on_yellow().bold()
+                } else {
+                    Style::new().reversed()
+                };
+                (m.range.clone(), style)
+            })
+            .collect();
+
+        if !ranges.is_empty() {
+            line.spans = split_spans(&line.spans, &ranges);
+        }
+    }
+}
+
+/// Splits spans at the range boundaries and patches the ranges' styles in
+fn split_spans(spans: &[Span<'static>], ranges: &[(Range<usize>, Style)]) -> Vec<Span<'static>> {
+    let mut result = Vec::new();
+    let mut offset = 0;
+
+    for span in spans {
+        let content = span.content.as_ref();
+        let end = offset + content.len();
+        let mut cursor = offset;
+
+        for (range, style) in ranges {
+            let from = range.start.max(cursor);
+            let to = range.end.min(end);
+            if from >= to {
+                continue;
+            }
+            if from > cursor {
+                result.push(Span::styled(
+                    content[cursor - offset..from - offset].to_owned(),
+                    span.style,
+                ));
+            }
+            result.push(Span::styled(
+                content[from - offset..to - offset].to_owned(),
+                span.style.patch(*style),
+            ));
+            cursor = to;
+        }
+
+        if cursor < end {
+            result.push(Span::styled(
+                content[cursor - offset..].to_owned(),
+                span.style,
+            ));
+        }
+        offset = end;
+    }
+
+    result
+}
+
+/// "2/3 (rust)"
+fn describe_block(block: &CodeBlock, index: usize, count: usize) -> String {
+    if block.lang.is_empty() {
+        format!("{}/{count}", index + 1)
+    } else {
+        format!("{}/{count} ({})", index + 1, block.lang)
+    }
+}
+
+/// Copies text to the system clipboard with an OSC 52 escape sequence.
+///
+/// The terminal does the copying, so this also works over SSH.
+pub fn copy_to_clipboard(text: &str) -> Result<()> {
+    let mut sequence = format!("\x1b]52;c;{}\x07", BASE64_STANDARD.encode(text));
+
+    // tmux only forwards the sequence to the outer terminal in a passthrough
+    if std::env::var_os("TMUX").is_some() {
+        sequence = format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"));
+    }
+
+    let mut out = stdout();
+    out.write_all(sequence.as_bytes())?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Where the answer view is drawn and what is left behind when it closes
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ViewOptions {
+    /// Draw below the prompt instead of on the alternate screen
+    pub inline: bool,
+    /// Print the answer to the normal screen after the view closes
+    pub keep_answer: bool,
+    /// Price of the model, to show the estimated cost with the token usage
+    pub price: Option<ModelPrice>,
+}
+
+/// Shows the streamed reply until the user quits.
+///
+/// Terminal events, stream events and a redraw tick are handled in the same
+/// loop, so keys keep working while the request is in flight. Returns the
+/// token usage if the provider reported it.
+pub fn handle_reply(
+    question: &str,
+    rx: Receiver<StreamEvent>,
+    cancel: CancelHandle,
+    options: ViewOptions,
+) -> Result<Option<Usage>> {
+    let mut app = App::new(question, options.price);
+
+    let mut terminal = if options.inline {
+        let (_, rows) = terminal::size()?;
+        ratatui::try_init_with_options(TerminalOptions {
+            viewport: Viewport::Inline((rows * 2 / 3).max(INLINE_MIN_HEIGHT)),
+        })
+        .inspect_err(|_| {
+            terminal::disable_raw_mode().ok();
+        })?
+    } else {
+        ratatui::init()
+    };
+    execute!(stdout(), EnableMouseCapture)?;
+    let result = run(&mut terminal, &mut app, rx, &cancel);
+    execute!(stdout(), DisableMouseCapture)?;
+
+    if options.inline {
+        // Leave the cursor at the top of the cleared view, where the answer
+        // is printed instead
+        terminal.clear()?;
+        terminal::disable_raw_mode()?;
+    } else {
+        ratatui::restore();
+    }
+
+    // Leaving the view while the answer is still streaming aborts the request
+    cancel.cancel();
+    result?;
+
+    if (options.inline || options.keep_answer) && !app.answer.is_empty() {
+        let (width, _) = terminal::size()?;
+        print_text(markdown::render(&app.answer, None), width)?;
+    }
+
+    match app.failure {
+        Some(err) => Err(err.into()),
+        None => Ok(app.usage),
+    }
+}
+
+/// Prints rendered text to the normal screen with ANSI styles, wrapped the
+/// same way as in the answer view
+pub fn print_text(text: Text<'static>, width: u16) -> Result<()> {
+    let paragraph = Paragraph::new(text).wrap(Wrap { trim: false });
+    let height = u16::try_from(paragraph.line_count(width)).unwrap_or(u16::MAX);
+    let area = Rect::new(0, 0, width, height);
+    let mut buffer = Buffer::empty(area);
+    paragraph.render(area, &mut buffer);
+
+    let mut out = stdout().lock();
+    for y in 0..height {
+        // Trailing blanks are dropped so copied lines don't end in spaces
+        let end = (0..width)
+            .rev()
+            .find(|&x| buffer[(x, y)].symbol() != " ")
+            .map_or(0, |x| x + 1);
+
+        let mut style = None;
+        let mut skip = 0;
+        for x in 0..end {
+            // Wide characters are followed by blank cells the terminal fills itself
+            if skip > 0 {
+                skip -= 1;
+                continue;
+            }
+            let cell = &buffer[(x, y)];
+            if style != Some(cell.style()) {
+                queue_style(&mut out, cell.style())?;
+                style = Some(cell.style());
+            }
+            out.write_all(cell.symbol().as_bytes())?;
+            skip = Span::from(cell.symbol()).width().saturating_sub(1);
+        }
+
+        queue!(out, SetAttribute(Attribute::Reset))?;
+        writeln!(out)?;
+    }
+    out.flush()?;
+
+    Ok(())
+}
+
+fn queue_style(out: &mut impl Write, style: Style) -> Result<()> {
+    queue!(out, SetAttribute(Attribute::Reset))?;
+    if let Some(fg) = style.fg {
+        queue!(out, SetForegroundColor(fg.into()))?;
+    }
+    if let Some(bg) = style.bg {
+        queue!(out, SetBackgroundColor(bg.into()))?;
+    }
+    for (modifier, attribute) in [
+        (Modifier::BOLD, Attribute::Bold),
+        (Modifier::DIM, Attribute::Dim),
+        (Modifier::ITALIC, Attribute::Italic),
+        (Modifier::UNDERLINED, Attribute::Underlined),
+        (Modifier::REVERSED, Attribute::Reverse),
+        (Modifier::CROSSED_OUT, Attribute::CrossedOut),
+    ] {
+        if style.add_modifier.contains(modifier) {
+            queue!(out, SetAttribute(attribute))?;
+        }
+    }
+    Ok(())
+}
+
+fn run(
+    terminal: &mut DefaultTerminal,
+    app: &mut App,
+    rx: Receiver<StreamEvent>,
+    cancel: &CancelHandle,
+) -> Result<()> {
+    let mut rx = Some(rx);
+
+    while !app.quit {
+        // Drain everything that arrived since the last frame
+        while let Some(receiver) = &rx {
+            match receiver.try_recv() {
+                Ok(event) => app.on_stream_event(event),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    if app.in_flight() {
+                        app.finish(Status::Done);
+                    }
+                    rx = None;
+                }
+            }
+        }
+
+        if !app.in_flight() && rx.is_some() {
+            cancel.cancel();
+            rx = None;
+        }
+
+        terminal.draw(|f| app.render(f))?;
+
+        match next_event()? {
+            Some(Event::Key(key)) if key.kind == KeyEventKind::Press => app.on_key(key),
+            Some(Event::Mouse(mouse)) => app.scroll.on_mouse(mouse),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
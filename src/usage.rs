@@ -0,0 +1,180 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::ai::Usage;
+use crate::settings::Settings;
+use crate::util::unix_now;
+
+/// File in the config directory that every request is logged to, one JSON object per line
+static USAGE_FILE: &str = "ask_usage.jsonl";
+static SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// One request in the usage log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageRecord {
+    /// Unix time in seconds
+    pub time: u64,
+    pub provider: String,
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl UsageRecord {
+    pub fn new(provider: &str, model: &str, preset: Option<&str>, usage: Usage) -> Self {
+        Self {
+            time: unix_now(),
+            provider: provider.to_owned(),
+            model: model.to_owned(),
+            preset: preset.map(str::to_owned),
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+        }
+    }
+}
+
+fn usage_path() -> PathBuf {
+    Settings::config_dir().join(USAGE_FILE)
+}
+
+/// Appends a request to the usage log
+pub fn record(record: &UsageRecord) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(usage_path())?;
+    writeln!(file, "{}", serde_json::to_string(record)?)?;
+    Ok(())
+}
+
+/// Reads the usage log, skipping lines that can't be parsed
+pub fn load() -> Result<Vec<UsageRecord>> {
+    let content = match std::fs::read_to_string(usage_path()) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+#[derive(Debug, Default)]
+struct Totals {
+    requests: u64,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    /// None if any of the requests has no price configured
+    cost: Option<f64>,
+}
+
+impl Totals {
+    fn add(&mut self, record: &UsageRecord, cost: Option<f64>) {
+        self.cost = match (self.requests, self.cost, cost) {
+            (0, _, cost) => cost,
+            (_, Some(total), Some(cost)) => Some(total + cost),
+            _ => None,
+        };
+        self.requests += 1;
+        self.prompt_tokens += record.prompt_tokens;
+        self.completion_tokens += record.completion_tokens;
+    }
+}
+
+/// Prints the usage of the last `days` days by day, model and preset.
+///
+/// Costs use the current price table, so prices can be configured afterwards.
+pub fn print_summary(settings: &Settings, days: u64) -> Result<()> {
+    let since = unix_now().saturating_sub(days * SECONDS_PER_DAY);
+    let records: Vec<UsageRecord> = load()?
+        .into_iter()
+        .filter(|record| record.time >= since)
+        .collect();
+
+    if records.is_empty() {
+        println!("No usage recorded in the last {days} days");
+        return Ok(());
+    }
+
+    let mut total = Totals::default();
+    let mut by_day = BTreeMap::<String, Totals>::new();
+    let mut by_model = BTreeMap::<String, Totals>::new();
+    let mut by_preset = BTreeMap::<String, Totals>::new();
+
+    for record in &records {
+        let cost = settings
+            .price(&record.model)
+            .map(|price| price.cost(record.prompt_tokens, record.completion_tokens));
+        let model = format!("{}/{}", record.provider, record.model);
+        let preset = record.preset.clone().unwrap_or_else(|| "(none)".to_owned());
+
+        total.add(record, cost);
+        by_day
+            .entry(date(record.time))
+            .or_default()
+            .add(record, cost);
+        by_model.entry(model).or_default().add(record, cost);
+        by_preset.entry(preset).or_default().add(record, cost);
+    }
+
+    print_table("By day (UTC)", &by_day);
+    print_table("By model", &by_model);
+    print_table("By preset", &by_preset);
+    print_table(
+        "Total",
+        &BTreeMap::from([(format!("last {days} days"), total)]),
+    );
+
+    Ok(())
+}
+
+fn print_table(title: &str, rows: &BTreeMap<String, Totals>) {
+    let width = rows
+        .keys()
+        .map(|key| key.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    println!("{title}");
+    for (key, totals) in rows {
+        let cost = totals
+            .cost
+            .map(|cost| format!("{cost:.6}"))
+            .unwrap_or_else(|| "-".to_owned());
+        println!(
+            "  {key:<width$}  requests {:>5}  prompt {:>9}  completion {:>9}  cost {cost:>10}",
+            totals.requests, totals.prompt_tokens, totals.completion_tokens,
+        );
+    }
+    println!();
+}
+
+/// Formats a unix time as a UTC date, e.g. "2025-08-30"
+fn date(time: u64) -> String {
+    // Converts days since the epoch to a civil date, after Howard Hinnant's
+    // `civil_from_days`
+    let days = (time / SECONDS_PER_DAY) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02}")
+}